@@ -0,0 +1,40 @@
+use bs64::{decode, decode_branchy, decode_simd, encode};
+use criterion::{
+    criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, Criterion, Throughput,
+};
+use std::{env::consts::ARCH, hint::black_box};
+
+const DATA_SIZE: usize = 1024 * 1024;
+
+trait CriterionExt {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime>;
+}
+
+impl CriterionExt for Criterion {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime> {
+        self.benchmark_group(format!("arch-{ARCH}/algo-{algo}/bench-{bench}"))
+    }
+}
+
+fn gen_data(length: usize) -> Vec<u8> {
+    (0..length as u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect()
+}
+
+fn decode_1mib(c: &mut Criterion) {
+    let encoded = encode(&gen_data(DATA_SIZE));
+    let mut g = c.my_benchmark_group("bs64", "decode");
+
+    g.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    g.bench_function("table", |b| b.iter(|| decode(black_box(&encoded))));
+    g.bench_function("simd", |b| b.iter(|| decode_simd(black_box(&encoded))));
+    g.bench_function("branchy", |b| {
+        b.iter(|| decode_branchy(black_box(&encoded)))
+    });
+
+    g.finish();
+}
+
+criterion_group!(bench, decode_1mib);
+criterion_main!(bench);
@@ -1,71 +1,443 @@
 #![feature(portable_simd)]
 
-pub fn encode(input: &[u8]) -> String {
-    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut output = String::new();
+use std::simd::{cmp::SimdPartialOrd, prelude::*, ToBytes};
+
+const STANDARD_TABLE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE_TABLE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
 
-    for chunk in input.chunks_exact(3) {
-        let c = (chunk[0] as u32) << 16 | (chunk[1] as u32) << 8 | chunk[2] as u32;
+const STANDARD_DECODE_TABLE: [u8; 256] = decode_table(STANDARD_TABLE);
+const URL_SAFE_DECODE_TABLE: [u8; 256] = decode_table(URL_SAFE_TABLE);
 
-        let b1 = ((c >> 18) & 0x3F) as usize;
-        let b2 = ((c >> 12) & 0x3F) as usize;
-        let b3 = ((c >> 6) & 0x3F) as usize;
-        let b4 = (c & 0x3F) as usize;
+/// Reverse of an alphabet, every byte outside of it maps to `255`
+const fn decode_table(alphabet: &[u8; 64]) -> [u8; 256] {
+    let mut table = [255u8; 256];
+    let mut i = 0;
 
-        output.push(TABLE[b1] as char);
-        output.push(TABLE[b2] as char);
-        output.push(TABLE[b3] as char);
-        output.push(TABLE[b4] as char);
+    while i < alphabet.len() {
+        table[alphabet[i] as usize] = i as u8;
+        i += 1;
     }
 
-    let rem = input.chunks_exact(3).remainder();
+    table
+}
 
-    match rem.len() {
-        0 => { /* Do nothing */ }
-        1 => {
-            let c = (rem[0] as u32) << 16;
-            let b1 = ((c >> 18) & 0x3F) as usize;
-            let b2 = ((c >> 12) & 0x3F) as usize;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    /// `A-Z a-z 0-9 + /`, RFC 4648 section 4
+    Standard,
+    /// `A-Z a-z 0-9 - _`, RFC 4648 section 5
+    UrlSafe,
+}
 
-            output.push(TABLE[b1] as char);
-            output.push(TABLE[b2] as char);
-            output.push_str("==");
+impl Alphabet {
+    const fn encode_table(self) -> &'static [u8; 64] {
+        match self {
+            Alphabet::Standard => STANDARD_TABLE,
+            Alphabet::UrlSafe => URL_SAFE_TABLE,
         }
-        2 => {
-            let c = (rem[0] as u32) << 16 | (rem[1] as u32) << 8;
+    }
+
+    const fn decode_table(self) -> &'static [u8; 256] {
+        match self {
+            Alphabet::Standard => &STANDARD_DECODE_TABLE,
+            Alphabet::UrlSafe => &URL_SAFE_DECODE_TABLE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    /// Output is padded with `=` to a multiple of 4 chars, and input must be
+    Padded,
+    /// No `=` is written, and any in the input is rejected
+    NoPad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// A char outside of the alphabet
+    InvalidChar,
+    /// The input can't be split into groups of 4 chars (or 2 to 4 unpadded)
+    BadLength,
+    /// `=` where no padding is allowed
+    BadPadding,
+}
+
+/// Why decoding failed, and at which byte of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub position: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl DecodeError {
+    /// Error for the rejected char at `position`
+    fn invalid_char(input: &[u8], position: usize) -> Self {
+        let kind = match input[position] {
+            b'=' => DecodeErrorKind::BadPadding,
+            _ => DecodeErrorKind::InvalidChar,
+        };
+
+        Self { position, kind }
+    }
+
+    fn bad_length(position: usize) -> Self {
+        Self {
+            position,
+            kind: DecodeErrorKind::BadLength,
+        }
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self.kind {
+            DecodeErrorKind::InvalidChar => "invalid char",
+            DecodeErrorKind::BadLength => "bad length",
+            DecodeErrorKind::BadPadding => "misplaced padding",
+        };
+
+        write!(f, "{what} at byte {}", self.position)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A base64 codec for one alphabet / padding combination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Base64 {
+    alphabet: Alphabet,
+    padding: Padding,
+}
+
+impl Base64 {
+    /// Padded standard alphabet, what [`encode`] and [`decode`] use
+    pub const STANDARD: Self = Self::new(Alphabet::Standard, Padding::Padded);
+
+    pub const fn new(alphabet: Alphabet, padding: Padding) -> Self {
+        Self { alphabet, padding }
+    }
+
+    pub fn encode(&self, input: &[u8]) -> String {
+        let table = self.alphabet.encode_table();
+        let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+        for chunk in input.chunks_exact(3) {
+            let c = (chunk[0] as u32) << 16 | (chunk[1] as u32) << 8 | chunk[2] as u32;
 
             let b1 = ((c >> 18) & 0x3F) as usize;
             let b2 = ((c >> 12) & 0x3F) as usize;
             let b3 = ((c >> 6) & 0x3F) as usize;
+            let b4 = (c & 0x3F) as usize;
+
+            output.push(table[b1] as char);
+            output.push(table[b2] as char);
+            output.push(table[b3] as char);
+            output.push(table[b4] as char);
+        }
+
+        let rem = input.chunks_exact(3).remainder();
 
-            output.push(TABLE[b1] as char);
-            output.push(TABLE[b2] as char);
-            output.push(TABLE[b3] as char);
-            output.push_str("=");
+        match rem.len() {
+            0 => { /* Do nothing */ }
+            1 => {
+                let c = (rem[0] as u32) << 16;
+                let b1 = ((c >> 18) & 0x3F) as usize;
+                let b2 = ((c >> 12) & 0x3F) as usize;
+
+                output.push(table[b1] as char);
+                output.push(table[b2] as char);
+
+                if self.padding == Padding::Padded {
+                    output.push_str("==");
+                }
+            }
+            2 => {
+                let c = (rem[0] as u32) << 16 | (rem[1] as u32) << 8;
+
+                let b1 = ((c >> 18) & 0x3F) as usize;
+                let b2 = ((c >> 12) & 0x3F) as usize;
+                let b3 = ((c >> 6) & 0x3F) as usize;
+
+                output.push(table[b1] as char);
+                output.push(table[b2] as char);
+                output.push(table[b3] as char);
+
+                if self.padding == Padding::Padded {
+                    output.push('=');
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        output
+    }
+
+    /// Table driven decode.
+    ///
+    /// Each group of 4 chars is looked up in the alphabet's reverse table and
+    /// packed into a 24-bit value, validity is checked once per group by
+    /// OR-ing the sextets, as any invalid char maps to `255` and poisons the
+    /// whole group.
+    pub fn decode(&self, input: &str) -> Option<Vec<u8>> {
+        self.try_decode(input).ok()
+    }
+
+    /// [`decode`](Self::decode), reporting where and why the input is
+    /// malformed.
+    ///
+    /// The position of a rejected char is only looked for once its group
+    /// failed the OR check, so the fast path stays branch free.
+    pub fn try_decode(&self, input: &str) -> Result<Vec<u8>, DecodeError> {
+        let input = input.as_bytes();
+        let table = self.alphabet.decode_table();
+        let (body, last) = self.split_last(input)?;
+        let mut output = Vec::with_capacity(input.len() / 4 * 3 + 2);
+
+        for (i, chunk) in body.chunks_exact(4).enumerate() {
+            let a = table[chunk[0] as usize];
+            let b = table[chunk[1] as usize];
+            let c = table[chunk[2] as usize];
+            let d = table[chunk[3] as usize];
+
+            if (a | b | c | d) & 0xC0 != 0 {
+                let j = chunk.iter().position(|&ch| table[ch as usize] == 255);
+                return Err(DecodeError::invalid_char(input, i * 4 + j.unwrap()));
+            }
+
+            let n = (a as u32) << 18 | (b as u32) << 12 | (c as u32) << 6 | d as u32;
+            output.extend_from_slice(&n.to_be_bytes()[1..]);
         }
-        _ => unreachable!(),
+
+        decode_last(input, body.len(), last, &mut output, |ch| {
+            table[ch as usize]
+        })?;
+
+        Ok(output)
     }
 
-    output
+    /// [`decode`](Self::decode) 16 chars at a time with `std::simd`.
+    ///
+    /// Chars are validated with range compares and mapped to sextets by
+    /// adding a per-range offset, then each group's 4 sextets are packed into
+    /// 3 bytes. Groups left over and the final (possibly padded) group are
+    /// decoded by the table.
+    pub fn decode_simd(&self, input: &str) -> Option<Vec<u8>> {
+        let input = input.as_bytes();
+        let table = self.alphabet.decode_table();
+        let (body, last) = self.split_last(input).ok()?;
+        let mut output = Vec::with_capacity(input.len() / 4 * 3 + 2);
+
+        let [c62, c63] = [62, 63].map(|i| self.alphabet.encode_table()[i]);
+        let blocks = body.chunks_exact(16);
+        let rest = blocks.remainder();
+
+        for block in blocks {
+            let chars = u8x16::from_slice(block);
+
+            let upper = chars.simd_ge(u8x16::splat(b'A')) & chars.simd_le(u8x16::splat(b'Z'));
+            let lower = chars.simd_ge(u8x16::splat(b'a')) & chars.simd_le(u8x16::splat(b'z'));
+            let digit = chars.simd_ge(u8x16::splat(b'0')) & chars.simd_le(u8x16::splat(b'9'));
+            let is62 = chars.simd_eq(u8x16::splat(c62));
+            let is63 = chars.simd_eq(u8x16::splat(c63));
+
+            if !(upper | lower | digit | is62 | is63).all() {
+                return None;
+            }
+
+            // sextet = char + offset of its range, mod 256
+            let offset = upper.select(u8x16::splat(0u8.wrapping_sub(b'A')), u8x16::splat(0));
+            let offset = lower.select(u8x16::splat(26u8.wrapping_sub(b'a')), offset);
+            let offset = digit.select(u8x16::splat(52u8.wrapping_sub(b'0')), offset);
+            let offset = is62.select(u8x16::splat(62u8.wrapping_sub(c62)), offset);
+            let offset = is63.select(u8x16::splat(63u8.wrapping_sub(c63)), offset);
+            let sextets = chars + offset;
+
+            // one group per lane: `a | b << 8 | c << 16 | d << 24` into the
+            // 24-bit `a << 18 | b << 12 | c << 6 | d`
+            let groups = u32x4::from_le_bytes(sextets);
+            let byte = u32x4::splat(0xff);
+            let packed = (groups & byte) << 18
+                | (groups >> 8 & byte) << 12
+                | (groups >> 16 & byte) << 6
+                | groups >> 24;
+
+            let bytes = packed.to_be_bytes();
+            let bytes: u8x16 =
+                simd_swizzle!(bytes, [1, 2, 3, 5, 6, 7, 9, 10, 11, 13, 14, 15, 0, 0, 0, 0]);
+            output.extend_from_slice(&bytes.as_array()[..12]);
+        }
+
+        for chunk in rest.chunks_exact(4) {
+            let n = chunk
+                .iter()
+                .try_fold(0u32, |n, &ch| match table[ch as usize] {
+                    255 => None,
+                    s => Some(n << 6 | s as u32),
+                })?;
+            output.extend_from_slice(&n.to_be_bytes()[1..]);
+        }
+
+        decode_last(input, body.len(), last, &mut output, |ch| {
+            table[ch as usize]
+        })
+        .ok()?;
+
+        Some(output)
+    }
+
+    /// Split the input into full groups of 4 chars, and the data chars (no
+    /// padding) of the final group
+    fn split_last<'a>(&self, input: &'a [u8]) -> Result<(&'a [u8], &'a [u8]), DecodeError> {
+        match self.padding {
+            Padding::Padded => {
+                if !input.len().is_multiple_of(4) {
+                    return Err(DecodeError::bad_length(input.len()));
+                }
+
+                let (body, last) = input.split_at(input.len().saturating_sub(4));
+                let last = match last {
+                    [a @ .., b'=', b'='] | [a @ .., b'='] => a,
+                    _ => last,
+                };
+
+                Ok((body, last))
+            }
+            Padding::NoPad => {
+                let rem = match input.len() % 4 {
+                    0 => input.len().min(4),
+                    1 => return Err(DecodeError::bad_length(input.len())),
+                    n => n,
+                };
+
+                Ok(input.split_at(input.len() - rem))
+            }
+        }
+    }
 }
 
+pub fn encode(input: &[u8]) -> String {
+    Base64::STANDARD.encode(input)
+}
+
+/// Decode padded standard base64, see [`Base64::decode`]
 pub fn decode(input: &str) -> Option<Vec<u8>> {
-    const TABLE: [u8; 256] = {
-        let mut table = [255u8; 256];
-        let bytes = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    Base64::STANDARD.decode(input)
+}
 
-        let mut i = 0;
+/// Decode padded standard base64, see [`Base64::try_decode`]
+pub fn try_decode(input: &str) -> Result<Vec<u8>, DecodeError> {
+    Base64::STANDARD.try_decode(input)
+}
+
+/// Decode padded standard base64, see [`Base64::decode_simd`]
+pub fn decode_simd(input: &str) -> Option<Vec<u8>> {
+    Base64::STANDARD.decode_simd(input)
+}
+
+/// Decode padded standard base64 one char at a time.
+///
+/// Reference implementation for [`decode`], maps every char through a range
+/// match and bails out on the first invalid one.
+pub fn decode_branchy(input: &str) -> Option<Vec<u8>> {
+    fn sextet(ch: u8) -> u8 {
+        match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => 255,
+        }
+    }
+
+    let (body, last) = Base64::STANDARD.split_last(input.as_bytes()).ok()?;
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+
+    for chunk in body.chunks_exact(4) {
+        let mut n = 0u32;
+
+        for &ch in chunk {
+            let s = sextet(ch);
+
+            if s == 255 {
+                return None;
+            }
 
-        while i < bytes.len() {
-            table[bytes[i] as usize] = i as u8;
-            i += 1;
+            n = n << 6 | s as u32;
         }
 
-        table
+        output.push((n >> 16) as u8);
+        output.push((n >> 8) as u8);
+        output.push(n as u8);
+    }
+
+    decode_last(input.as_bytes(), body.len(), last, &mut output, sextet).ok()?;
+
+    Some(output)
+}
+
+/// Lowercase hex of `bytes`, for eyeballing decoded output
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Where `actual` first differs from `expected`, with up to 8 bytes of each
+/// from there in hex, or `None` if they're equal. Meant for checking a
+/// decode against known bytes.
+pub fn hex_diff(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let at = expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .unwrap_or(expected.len().min(actual.len()));
+
+    if at == expected.len() && at == actual.len() {
+        return None;
+    }
+
+    let window = |bytes: &[u8]| to_hex(&bytes[at..bytes.len().min(at + 8)]);
+
+    Some(format!(
+        "differ at byte {at}: expected {}, got {}",
+        window(expected),
+        window(actual)
+    ))
+}
+
+/// Decode the 2 to 4 data chars of the final group, which starts at byte
+/// `offset` of `input`
+fn decode_last(
+    input: &[u8],
+    offset: usize,
+    last: &[u8],
+    output: &mut Vec<u8>,
+    sextet: impl Fn(u8) -> u8,
+) -> Result<(), DecodeError> {
+    let n_bytes = match last.len() {
+        0 => return Ok(()),
+        1 => return Err(DecodeError::bad_length(offset + 1)),
+        n => n - 1,
     };
 
-    let len = input.len();
-    return None;
+    let mut n = 0u32;
+
+    for (i, &ch) in last.iter().enumerate() {
+        let s = sextet(ch);
+
+        if s == 255 {
+            return Err(DecodeError::invalid_char(input, offset + i));
+        }
+
+        n = n << 6 | s as u32;
+    }
+
+    n <<= 6 * (3 - n_bytes) as u32;
+    output.extend_from_slice(&n.to_be_bytes()[1..n_bytes + 1]);
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -76,4 +448,184 @@ mod tests {
     fn it_works() {
         assert_eq!(String::from("TXkgU3RyaW5n"), encode("My String".as_bytes()));
     }
+
+    #[test]
+    fn test_decode_known_values() {
+        assert_eq!(decode(""), Some(vec![]));
+        assert_eq!(decode("TXkgU3RyaW5n"), Some(b"My String".to_vec()));
+        assert_eq!(decode("TXkgU3RyaW4="), Some(b"My Strin".to_vec()));
+        assert_eq!(decode("TXkgU3RyaQ=="), Some(b"My Stri".to_vec()));
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_input() {
+        assert_eq!(decode("TXkgU3RyaW5"), None);
+        assert_eq!(decode("TXkg*3RyaW5n"), None);
+        assert_eq!(decode("TXkgU3RyaW5\u{e9}"), None);
+        assert_eq!(decode("TX=gU3RyaW5n"), None);
+        assert_eq!(decode("TXkgU3Ry=W5n"), None);
+        assert_eq!(decode("TQ=="), Some(b"M".to_vec()));
+        assert_eq!(decode("T==="), None);
+    }
+
+    #[test]
+    fn test_decode_error_positions() {
+        let err = |position, kind| Err(DecodeError { position, kind });
+
+        assert_eq!(
+            try_decode("TXkg*3RyaW5n"),
+            err(4, DecodeErrorKind::InvalidChar)
+        );
+        assert_eq!(
+            try_decode("TXkgU3RyaW\u{e9}"),
+            err(10, DecodeErrorKind::InvalidChar)
+        );
+        assert_eq!(
+            try_decode("TXkgU3Ry*W4="),
+            err(8, DecodeErrorKind::InvalidChar)
+        );
+        assert_eq!(
+            try_decode("TX=gU3RyaW5n"),
+            err(2, DecodeErrorKind::BadPadding)
+        );
+        assert_eq!(try_decode("T==="), err(1, DecodeErrorKind::BadPadding));
+        assert_eq!(
+            try_decode("TXkgU3RyaW5"),
+            err(11, DecodeErrorKind::BadLength)
+        );
+
+        let nopad = Base64::new(Alphabet::Standard, Padding::NoPad);
+        assert_eq!(
+            nopad.try_decode("TQ=="),
+            err(2, DecodeErrorKind::BadPadding)
+        );
+        assert_eq!(
+            nopad.try_decode("TXkgU"),
+            err(5, DecodeErrorKind::BadLength)
+        );
+    }
+
+    #[test]
+    fn test_hex_helpers() {
+        assert_eq!(to_hex(b""), "");
+        assert_eq!(to_hex(&[0x00, 0x7f, 0xab]), "007fab");
+
+        let decoded = decode("TXkgU3RyaW5n").unwrap();
+        assert_eq!(hex_diff(b"My String", &decoded), None);
+        assert_eq!(
+            hex_diff(b"My Strong", &decoded).as_deref(),
+            Some("differ at byte 6: expected 6f6e67, got 696e67")
+        );
+        assert_eq!(
+            hex_diff(b"My String!", &decoded).as_deref(),
+            Some("differ at byte 9: expected 21, got ")
+        );
+    }
+
+    #[test]
+    fn test_table_decode_matches_branchy_decode() {
+        let data: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+
+        for len in 0..data.len().min(300) {
+            let encoded = encode(&data[..len]);
+
+            assert_eq!(decode(&encoded), Some(data[..len].to_vec()));
+            assert_eq!(decode(&encoded), decode_branchy(&encoded));
+        }
+
+        let encoded = encode(&data);
+
+        for i in 0..encoded.len().min(400) {
+            for bad in ["*", "-", "_", "="] {
+                let mut corrupted = encoded.clone();
+                corrupted.replace_range(i..i + 1, bad);
+
+                assert_eq!(decode(&corrupted), decode_branchy(&corrupted));
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_variants_round_trip() {
+        let data: Vec<u8> = (0..=255u8).rev().collect();
+
+        for alphabet in [Alphabet::Standard, Alphabet::UrlSafe] {
+            for padding in [Padding::Padded, Padding::NoPad] {
+                let engine = Base64::new(alphabet, padding);
+
+                for len in 0..data.len() {
+                    let encoded = engine.encode(&data[..len]);
+
+                    assert_eq!(
+                        encoded.contains('='),
+                        padding == Padding::Padded && len % 3 != 0
+                    );
+                    assert_eq!(engine.decode(&encoded), Some(data[..len].to_vec()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_variants_differ_where_expected() {
+        let data = [0xfb, 0xff, 0xbf, 0xfe];
+
+        let std_pad = Base64::new(Alphabet::Standard, Padding::Padded);
+        let std_nopad = Base64::new(Alphabet::Standard, Padding::NoPad);
+        let url_pad = Base64::new(Alphabet::UrlSafe, Padding::Padded);
+        let url_nopad = Base64::new(Alphabet::UrlSafe, Padding::NoPad);
+
+        assert_eq!(Base64::STANDARD, std_pad);
+        assert_eq!(std_pad.encode(&data), "+/+//g==");
+        assert_eq!(std_nopad.encode(&data), "+/+//g");
+        assert_eq!(url_pad.encode(&data), "-_-__g==");
+        assert_eq!(url_nopad.encode(&data), "-_-__g");
+
+        assert_eq!(std_pad.decode("+/+//g"), None);
+        assert_eq!(std_nopad.decode("+/+//g=="), None);
+        assert_eq!(url_pad.decode("+/+//g=="), None);
+        assert_eq!(std_pad.decode("-_-__g=="), None);
+        assert_eq!(url_nopad.decode("-_-__"), None);
+    }
+
+    #[test]
+    fn test_simd_decode_matches_table_decode() {
+        // xorshift, so lengths and contents vary without a rand dependency
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..500 {
+            let len = next() as usize % 200;
+            let data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+
+            for alphabet in [Alphabet::Standard, Alphabet::UrlSafe] {
+                for padding in [Padding::Padded, Padding::NoPad] {
+                    let engine = Base64::new(alphabet, padding);
+                    let encoded = engine.encode(&data);
+
+                    assert_eq!(engine.decode_simd(&encoded), Some(data.clone()));
+
+                    if encoded.is_empty() {
+                        continue;
+                    }
+
+                    // any corrupted char rejects the whole input, as it does
+                    // for the table decoder
+                    let mut corrupted = encoded.into_bytes();
+                    let i = next() as usize % corrupted.len();
+                    corrupted[i] = b"*=-_+/\x80"[next() as usize % 7];
+                    let corrupted = String::from_utf8_lossy(&corrupted);
+
+                    assert_eq!(engine.decode_simd(&corrupted), engine.decode(&corrupted));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,68 @@
+use criterion::{
+    criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, Criterion, Throughput,
+};
+use kvdb::{ConcurrentStore, PartedHash, Store};
+use std::{env::consts::ARCH, sync::Mutex, thread};
+use tempfile::TempDir;
+
+const SHARDS: usize = 8;
+const KEYS: u32 = 16_000;
+
+trait CriterionExt {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime>;
+}
+
+impl CriterionExt for Criterion {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime> {
+        self.benchmark_group(format!("arch-{ARCH}/algo-{algo}/bench-{bench}"))
+    }
+}
+
+/// One writer per shard, each setting the keys of its shard
+fn write_all(db: &ConcurrentStore, buckets: &[Vec<[u8; 4]>], global: Option<&Mutex<()>>) {
+    thread::scope(|s| {
+        for bucket in buckets {
+            s.spawn(move || {
+                for k in bucket {
+                    let _guard = global.map(|lock| lock.lock().unwrap());
+                    db.set(k, k).unwrap();
+                }
+            });
+        }
+    });
+}
+
+fn concurrent_set(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let mut db = Store::open(dir.path()).unwrap();
+
+    while db.shard_ranges().len() < SHARDS {
+        for idx in (0..db.shard_ranges().len()).rev() {
+            db.split(idx).unwrap();
+        }
+    }
+
+    let ranges = db.shard_ranges();
+    let mut buckets = vec![vec![]; SHARDS];
+    for i in 0..KEYS {
+        let shard = PartedHash::new(&i.to_le_bytes()).shard();
+        let idx = ranges.iter().position(|&(_, end)| shard < end).unwrap();
+        buckets[idx].push(i.to_le_bytes());
+    }
+
+    let db = db.into_concurrent().unwrap();
+    let global = Mutex::new(());
+
+    let mut g = c.my_benchmark_group("kvdb", "concurrent_set");
+    g.throughput(Throughput::Elements(KEYS.into()));
+
+    g.bench_function("shard_locks", |b| b.iter(|| write_all(&db, &buckets, None)));
+    g.bench_function("global_lock", |b| {
+        b.iter(|| write_all(&db, &buckets, Some(&global)))
+    });
+
+    g.finish();
+}
+
+criterion_group!(benches, concurrent_set);
+criterion_main!(benches);
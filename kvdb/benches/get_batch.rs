@@ -0,0 +1,53 @@
+use criterion::{
+    criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, Criterion, Throughput,
+};
+use kvdb::Store;
+use std::{env::consts::ARCH, hint::black_box};
+use tempfile::TempDir;
+
+const STORE_KEYS: u32 = 200_000;
+const BATCH: u32 = 10_000;
+
+trait CriterionExt {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime>;
+}
+
+impl CriterionExt for Criterion {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime> {
+        self.benchmark_group(format!("arch-{ARCH}/algo-{algo}/bench-{bench}"))
+    }
+}
+
+fn get_batch(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let mut db = Store::open(dir.path()).unwrap();
+
+    for i in 0..STORE_KEYS {
+        db.set(&i.to_le_bytes(), &i.to_be_bytes()).unwrap();
+    }
+
+    // spread over the whole store, in no particular row order
+    let keys: Vec<[u8; 4]> = (0..BATCH)
+        .map(|i| (i.wrapping_mul(7919) % STORE_KEYS).to_le_bytes())
+        .collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+
+    let mut g = c.my_benchmark_group("kvdb", "get_batch");
+    g.throughput(Throughput::Elements(BATCH.into()));
+
+    g.bench_function("unsorted", |b| {
+        b.iter(|| {
+            let vals: Vec<_> = keys.iter().map(|k| db.get(k).unwrap()).collect();
+            black_box(vals)
+        })
+    });
+
+    g.bench_function("sorted", |b| {
+        b.iter(|| black_box(db.get_sorted(&keys).unwrap()))
+    });
+
+    g.finish();
+}
+
+criterion_group!(benches, get_batch);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+use criterion::{
+    criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, Criterion,
+};
+use kvdb::{PartedHash, ShardFile};
+use std::{env::consts::ARCH, fs::OpenOptions, hint::black_box};
+use tempfile::TempDir;
+
+const WIDTH: u32 = 512;
+
+trait CriterionExt {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime>;
+}
+
+impl CriterionExt for Criterion {
+    fn my_benchmark_group(&mut self, algo: &str, bench: &str) -> BenchmarkGroup<'_, WallTime> {
+        self.benchmark_group(format!("arch-{ARCH}/algo-{algo}/bench-{bench}"))
+    }
+}
+
+fn row_scan(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let mut opts = OpenOptions::new();
+    opts.read(true).write(true);
+    let shard = ShardFile::create(&opts, dir.path(), 0, 1 << 16).unwrap();
+
+    // fill row 0 with distinct signs, none of them the one looked up below
+    for sign in 1..=WIDTH {
+        let ph = PartedHash::from_parts(sign, 0, 0);
+        assert!(shard.set(ph, &sign.to_le_bytes(), b"v").unwrap());
+    }
+
+    let mut g = c.my_benchmark_group("kvdb", "row_scan");
+
+    g.bench_function("full_row_miss", |b| {
+        let ph = PartedHash::from_parts(WIDTH + 1, 0, 0);
+        b.iter(|| black_box(shard.get(ph, b"missing").unwrap()))
+    });
+
+    g.bench_function("full_row_last_slot", |b| {
+        let ph = PartedHash::from_parts(WIDTH, 0, 0);
+        b.iter(|| black_box(shard.get(ph, &WIDTH.to_le_bytes()).unwrap()))
+    });
+
+    g.finish();
+}
+
+criterion_group!(benches, row_scan);
+criterion_main!(benches);
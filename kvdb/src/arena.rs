@@ -0,0 +1,147 @@
+use std::os::unix::fs::FileExt;
+
+use crate::{Buf, PartedHash, Result, ShardFile, Store};
+
+/// Where a value read by [`Store::get_into_arena`] lives in its [`ReadArena`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueRef {
+    pub start: usize,
+    pub len: usize,
+}
+
+/// One allocation holding every value of a batch of lookups, see
+/// [`Store::get_into_arena`]
+#[derive(Default)]
+pub struct ReadArena {
+    values: Buf,
+    key_buf: Buf,
+}
+
+impl ReadArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(bytes: usize) -> Self {
+        Self {
+            values: Vec::with_capacity(bytes),
+            key_buf: Buf::new(),
+        }
+    }
+
+    pub fn get(&self, vr: ValueRef) -> &[u8] {
+        &self.values[vr.start..vr.start + vr.len]
+    }
+
+    /// Forget all values, keeping the allocation. Earlier [`ValueRef`]s no
+    /// longer point at their values.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl ShardFile {
+    /// [`get`](Self::get) appending the value to `arena`
+    pub fn get_into_arena(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        arena: &mut ReadArena,
+    ) -> Result<Option<ValueRef>> {
+        let row = self.header_row(ph.row());
+
+        for i in row.slots_with(ph.sign()).rev() {
+            let desc = row.descriptors[i];
+            let key_buf = &mut arena.key_buf;
+
+            #[cfg(test)]
+            crate::counters::count_read();
+
+            key_buf.resize(desc.klen(), 0);
+            let f = &self.file;
+            f.read_exact_at(key_buf, desc.offset as u64)?;
+
+            if key_buf == key {
+                if desc.is_tombstone() {
+                    return Ok(None);
+                }
+
+                let start = arena.values.len();
+                arena.values.resize(start + desc.vlen as usize, 0);
+
+                if let Err(e) = f.read_exact_at(
+                    &mut arena.values[start..],
+                    desc.offset as u64 + desc.klen() as u64,
+                ) {
+                    arena.values.truncate(start);
+                    return Err(e.into());
+                }
+
+                return Ok(Some(ValueRef {
+                    start,
+                    len: desc.vlen as usize,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Store {
+    /// [`get`](Self::get) appending the value to `arena` instead of
+    /// allocating it, so all values of a batch of lookups share one buffer
+    pub fn get_into_arena(&self, key: &[u8], arena: &mut ReadArena) -> Result<Option<ValueRef>> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        let ph = self.hasher.hash(key);
+
+        let vr = self.shards[self.shard_idx(ph)].get_into_arena(ph, key, arena)?;
+        self.notify(started, |o, elapsed| {
+            o.on_get(elapsed, key.len(), vr.is_some())
+        });
+
+        Ok(vr)
+    }
+}
+
+#[cfg(test)]
+mod arena_tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_arena_holds_values_of_many_lookups() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&i.to_le_bytes(), format!("value-{i}").as_bytes())
+                .unwrap();
+        }
+
+        let mut arena = ReadArena::new();
+        let refs: Vec<_> = (0..1000u32)
+            .map(|i| db.get_into_arena(&i.to_le_bytes(), &mut arena).unwrap())
+            .collect();
+
+        assert_eq!(db.get_into_arena(b"missing", &mut arena).unwrap(), None);
+
+        for (i, vr) in refs.into_iter().enumerate() {
+            assert_eq!(arena.get(vr.unwrap()), format!("value-{i}").as_bytes());
+        }
+        assert_eq!(
+            arena.len(),
+            (0..1000).map(|i| format!("value-{i}").len()).sum()
+        );
+    }
+}
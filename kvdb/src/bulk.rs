@@ -0,0 +1,183 @@
+use std::{io::ErrorKind, ops::Range};
+
+use crate::{sync_dir, Descriptor, PartedHash, Result, ShardFile, Store, ROWS, WIDTH};
+
+/// `[start, end)` of a planned shard, with the span of the hash-sorted items
+/// going into it
+type Plan = (u32, u32, Range<usize>);
+
+impl Store {
+    /// Load `items` into an empty store in one go, for a one-time import.
+    ///
+    /// Keys are hashed upfront to pick shard ranges that fit them, so nothing
+    /// is split along the way. Each shard's data region is then written in a
+    /// single sequential pass, in the order of `items`, and its header filled
+    /// afterwards with [`ShardFile::rebuild_index`].
+    ///
+    /// Keys must be unique, as with [`set_blind`](Self::set_blind). The load
+    /// checkpoints the write-ahead log and then bypasses it and the observer, and a failure midway leaves
+    /// the store to be opened with [`truncate`](crate::StoreBuilder::truncate).
+    pub fn bulk_load(&mut self, items: &[(&[u8], &[u8])]) -> Result<()> {
+        if self.shards.iter().any(|shard| shard.live_slots() > 0) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "bulk_load needs an empty store",
+            )
+            .into());
+        }
+
+        let keys: Vec<_> = items.iter().map(|(k, _)| self.normalize(k)).collect();
+        for (k, (_, v)) in keys.iter().zip(items) {
+            Descriptor::check_lens(k, v)?;
+        }
+
+        // checkpoint the log, so its records aren't replayed over the load
+        self.sync()?;
+
+        let mut order: Vec<(PartedHash, usize)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (self.hasher.hash(k), i))
+            .collect();
+        order.sort_unstable_by_key(|&(ph, i)| (ph.shard(), i));
+
+        let mut plans = Vec::new();
+        Self::plan_shards(&order, 0, Self::MAX_SHARD, 0, &mut plans)?;
+
+        for shard in std::mem::take(&mut self.shards) {
+            let path = self.dirpath.join(format!("{}-{}", shard.start, shard.end));
+            drop(shard);
+            std::fs::remove_file(path)?;
+        }
+
+        for (start, end, span) in plans {
+            let shard = ShardFile::create(&self.opts, &self.dirpath, start, end)?;
+
+            let records: Vec<(&[u8], &[u8])> = order[span]
+                .iter()
+                .map(|&(_, i)| (&*keys[i], items[i].1))
+                .collect();
+            let descs = shard.append_raw(&records)?;
+
+            let entries: Vec<_> = records
+                .iter()
+                .zip(descs)
+                .map(|(&(k, _), d)| (k, d))
+                .collect();
+            shard.rebuild_index(self.hasher, &entries)?;
+            shard.sync()?;
+
+            self.shards.push(shard);
+        }
+
+        sync_dir(&self.dirpath)
+    }
+
+    /// Halve `[start, end)` the way [`split`](Self::split) does until the
+    /// items of each half fit its rows. `order` holds the items of the range,
+    /// starting at `base` in the whole.
+    fn plan_shards(
+        order: &[(PartedHash, usize)],
+        start: u32,
+        end: u32,
+        base: usize,
+        plans: &mut Vec<Plan>,
+    ) -> Result<()> {
+        let mut rows = [0usize; ROWS];
+        for (ph, _) in order {
+            rows[ph.row()] += 1;
+        }
+
+        if rows.iter().all(|&n| n <= WIDTH) {
+            plans.push((start, end, base..base + order.len()));
+            return Ok(());
+        }
+
+        if end - start == 1 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("over {WIDTH} keys in a row of shard {start}-{end}"),
+            )
+            .into());
+        }
+
+        let mid = (start + end) / 2;
+        let split = order.partition_point(|(ph, _)| ph.shard() < mid);
+
+        Self::plan_shards(&order[..split], start, mid, base, plans)?;
+        Self::plan_shards(&order[split..], mid, end, base + split, plans)
+    }
+}
+
+#[cfg(test)]
+mod bulk_tests {
+    use super::*;
+    use crate::{Observer, StoreBuilder};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    struct NoSplits;
+
+    impl Observer for NoSplits {
+        fn on_split(&self, _: Duration, shard: (u32, u32)) {
+            panic!("split {shard:?} during a bulk load");
+        }
+    }
+
+    #[test]
+    fn test_bulk_load_fills_presplit_shards() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::with_observer(dir.path(), NoSplits).unwrap();
+
+        let kvs: Vec<_> = (0..1_000_000u32)
+            .map(|i| (i.to_be_bytes(), i.to_le_bytes()))
+            .collect();
+        let items: Vec<(&[u8], &[u8])> = kvs.iter().map(|(k, v)| (&k[..], &v[..])).collect();
+
+        db.bulk_load(&items).unwrap();
+
+        let ranges = db.shard_ranges();
+        assert!(ranges.len() > 1);
+        assert_eq!(ranges.first().unwrap().0, 0);
+        assert_eq!(ranges.last().unwrap().1, Store::MAX_SHARD);
+        assert!(ranges.windows(2).all(|w| w[0].1 == w[1].0));
+
+        for (k, v) in kvs.iter() {
+            assert_eq!(db.get(k).unwrap(), Some(v.to_vec()));
+        }
+        assert_eq!(db.estimated_key_count(), 1_000_000);
+        assert!(db.verify().unwrap().is_ok());
+
+        assert!(db.bulk_load(&items[..1]).is_err());
+        drop(db);
+
+        let db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.shard_ranges(), ranges);
+        assert_eq!(
+            db.get(&7u32.to_be_bytes()).unwrap(),
+            Some(7u32.to_le_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_bulk_load_normalizes_keys_and_checkpoints_the_log() {
+        let dir = TempDir::new().unwrap();
+        let builder = StoreBuilder::new()
+            .wal(true)
+            .key_normalizer(|k| k.to_ascii_lowercase());
+
+        let mut db = builder.open(dir.path()).unwrap();
+        db.set(b"k", b"old").unwrap();
+        db.remove(b"k").unwrap();
+
+        db.bulk_load(&[(b"K", b"new"), (b"Other", b"v")]).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(db.get(b"OTHER").unwrap(), Some(b"v".to_vec()));
+        std::mem::forget(db);
+
+        // nothing left in the log to replay over the loaded keys
+        let db = builder.open(dir.path()).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(db.get(b"other").unwrap(), Some(b"v".to_vec()));
+    }
+}
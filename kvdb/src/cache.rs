@@ -0,0 +1,154 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use crate::{Buf, Result, Store};
+
+/// Least recently used `key -> value` entries, evicted by access tick
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<Buf, (Buf, u64)>,
+    by_tick: BTreeMap<u64, Buf>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            by_tick: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Buf> {
+        let (val, tick) = self.entries.get_mut(key)?;
+
+        let key = self.by_tick.remove(tick).unwrap();
+        self.tick += 1;
+        *tick = self.tick;
+        self.by_tick.insert(self.tick, key);
+
+        Some(val.clone())
+    }
+
+    fn insert(&mut self, key: &[u8], val: Buf) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.remove(key);
+
+        if self.entries.len() == self.capacity {
+            let (_, oldest) = self.by_tick.pop_first().unwrap();
+            self.entries.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.entries.insert(key.to_vec(), (val, self.tick));
+        self.by_tick.insert(self.tick, key.to_vec());
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((_, tick)) = self.entries.remove(key) {
+            self.by_tick.remove(&tick);
+        }
+    }
+}
+
+/// A [`Store`] serving repeated [`get`](Self::get)s of hot keys from memory,
+/// see [`Store::with_cache`].
+///
+/// Only values that were found are cached. Writes go through
+/// [`set`](Self::set) and [`remove`](Self::remove), which drop the cached
+/// entry of their key, so the store itself is only lent out read-only.
+pub struct CachedStore {
+    store: Store,
+    cache: RefCell<Lru>,
+}
+
+impl CachedStore {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Buf>> {
+        let key = self.store.normalize(key);
+        let key = &*key;
+
+        if let Some(val) = self.cache.borrow_mut().get(key) {
+            return Ok(Some(val));
+        }
+
+        let val = self.store.get(key)?;
+
+        if let Some(val) = &val {
+            self.cache.borrow_mut().insert(key, val.clone());
+        }
+
+        Ok(val)
+    }
+
+    pub fn set(&mut self, key: &[u8], val: &[u8]) -> Result<bool> {
+        self.cache.get_mut().remove(&self.store.normalize(key));
+        self.store.set(key, val)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Result<bool> {
+        self.cache.get_mut().remove(&self.store.normalize(key));
+        self.store.remove(key)
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Drop the cache and hand back the store
+    pub fn into_inner(self) -> Store {
+        self.store
+    }
+}
+
+impl Store {
+    /// [`open`](Self::open), caching up to `capacity` recently read values
+    pub fn with_cache(dir: impl AsRef<Path>, capacity: usize) -> Result<CachedStore> {
+        Ok(CachedStore {
+            store: Self::open(dir)?,
+            cache: RefCell::new(Lru::new(capacity)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use crate::counters;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cached_get_skips_file_reads() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::with_cache(dir.path(), 2).unwrap();
+
+        for k in [b"a", b"b", b"c"] {
+            db.set(k, k).unwrap();
+        }
+
+        assert_eq!(db.get(b"a").unwrap(), Some(b"a".to_vec()));
+        let reads = counters::reads();
+        assert_eq!(db.get(b"a").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(counters::reads(), reads, "a cached key must not be read");
+
+        // "b" and "c" push "a" out
+        db.get(b"b").unwrap();
+        db.get(b"c").unwrap();
+        let reads = counters::reads();
+        db.get(b"a").unwrap();
+        assert!(counters::reads() > reads);
+
+        db.set(b"a", b"new").unwrap();
+        assert_eq!(db.get(b"a").unwrap(), Some(b"new".to_vec()));
+
+        db.remove(b"a").unwrap();
+        assert_eq!(db.get(b"a").unwrap(), None);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    borrow::Cow,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use crate::{
+    sync_dir, Buf, Descriptor, KeyHasher, KeyNormalizer, PartedHash, Result, ShardFile, Store, ROWS,
+};
+
+/// A shard with its range, kept outside the lock for lookups
+type Entry = ((u32, u32), Mutex<ShardFile>);
+
+/// A [`Store`] shared between threads, with a lock per shard so writes to
+/// different shards run in parallel, see [`Store::into_concurrent`].
+///
+/// Only [`split`](Self::split) takes the whole store, to replace a shard by
+/// its halves.
+pub struct ConcurrentStore {
+    dirpath: PathBuf,
+    opts: OpenOptions,
+    key_normalizer: Option<KeyNormalizer>,
+    tombstones: bool,
+    hasher: KeyHasher,
+    shards: RwLock<Vec<Entry>>,
+}
+
+impl ConcurrentStore {
+    /// [`Store::open`] the store in `dir` for use from many threads
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Store::open(dir)?.into_concurrent()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Buf>> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let ph = self.hasher.hash(key);
+        let shards = self.shards.read().unwrap();
+
+        let shard = shards[Self::shard_idx(&shards, ph)].1.lock().unwrap();
+        shard.get(ph, key)
+    }
+
+    pub fn set(&self, key: &[u8], val: &[u8]) -> Result<bool> {
+        let key = self.normalize(key);
+        let key = &*key;
+        Descriptor::check_lens(key, val)?;
+        let ph = self.hasher.hash(key);
+
+        {
+            let shards = self.shards.read().unwrap();
+            let shard = shards[Self::shard_idx(&shards, ph)].1.lock().unwrap();
+
+            if shard.set(ph, key, val)? {
+                return Ok(true);
+            }
+        }
+
+        // the row is full, retry under the store lock as another writer may
+        // have split the shard in between
+        let mut shards = self.shards.write().unwrap();
+
+        loop {
+            let idx = Self::shard_idx(&shards, ph);
+
+            if shards[idx].1.get_mut().unwrap().set(ph, key, val)? {
+                return Ok(true);
+            }
+
+            self.split_locked(&mut shards, idx)?;
+        }
+    }
+
+    pub fn remove(&self, key: &[u8]) -> Result<bool> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let ph = self.hasher.hash(key);
+        let shards = self.shards.read().unwrap();
+
+        let mut shard = shards[Self::shard_idx(&shards, ph)].1.lock().unwrap();
+        match self.tombstones {
+            true => shard.tombstone(ph, key),
+            false => shard.remove(ph, key),
+        }
+    }
+
+    /// [`Store::split`], waiting for the operations in flight on any shard
+    pub fn split(&self, shard_idx: usize) -> Result<()> {
+        let mut shards = self.shards.write().unwrap();
+        self.split_locked(&mut shards, shard_idx)
+    }
+
+    /// [`Store::compact_shard`] without holding up the store. Live entries
+    /// are copied a row at a time, taking the shard's lock per row only, so
+    /// reads and writes carry on against the old file meanwhile.
+    ///
+    /// The store is locked for the final step alone: rows written to since
+    /// they were copied are copied again, and the copy is renamed over the
+    /// shard. A shard split in between is left alone, the copy discarded.
+    pub fn compact_shard(&self, shard_idx: usize) -> Result<()> {
+        let range = self.shards.read().unwrap()[shard_idx].0;
+        let (start, end) = range;
+        let tmppath = self.dirpath.join(ShardFile::tmp_name(start, end));
+
+        let res = (|| {
+            let compacted = ShardFile::create_at(&self.opts, tmppath.clone(), start, end)?;
+            let mut copied = Vec::with_capacity(ROWS);
+
+            for r in 0..ROWS {
+                let shards = self.shards.read().unwrap();
+                let Some((_, shard)) = shards.iter().find(|(rng, _)| *rng == range) else {
+                    return Ok(false);
+                };
+
+                copied.push(compacted.copy_row(&shard.lock().unwrap(), r)?);
+            }
+
+            let mut shards = self.shards.write().unwrap();
+            let Some(idx) = shards.iter().position(|(rng, _)| *rng == range) else {
+                return Ok(false);
+            };
+            let shard = shards[idx].1.get_mut().unwrap();
+
+            for (r, row) in copied.iter().enumerate() {
+                if shard.header_row(r) != row {
+                    compacted.copy_row(shard, r)?;
+                }
+            }
+            compacted.sync()?;
+
+            std::fs::rename(&tmppath, self.dirpath.join(format!("{start}-{end}")))?;
+            shards[idx] = Self::entry(compacted);
+            sync_dir(&self.dirpath)?;
+
+            Ok(true)
+        })();
+
+        if !matches!(res, Ok(true)) {
+            let _ = std::fs::remove_file(&tmppath);
+        }
+
+        res.map(drop)
+    }
+
+    fn split_locked(&self, shards: &mut Vec<Entry>, shard_idx: usize) -> Result<()> {
+        let (top, bottom) = Store::split_files(
+            &self.opts,
+            &self.dirpath,
+            self.hasher,
+            shards[shard_idx].1.get_mut().unwrap(),
+        )?;
+
+        shards[shard_idx] = Self::entry(top);
+        shards.push(Self::entry(bottom));
+        shards.sort_by_key(|((_, end), _)| *end);
+
+        sync_dir(&self.dirpath)
+    }
+
+    /// [`Store::shard_ranges`]
+    pub fn shard_ranges(&self) -> Vec<(u32, u32)> {
+        self.shards
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(range, _)| *range)
+            .collect()
+    }
+
+    /// [`Store::normalize`]
+    fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        match self.key_normalizer {
+            Some(f) => Cow::Owned(f(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    fn shard_idx(shards: &[Entry], ph: PartedHash) -> usize {
+        shards
+            .iter()
+            .position(|((_, end), _)| ph.shard() < *end)
+            .unwrap()
+    }
+
+    fn entry(shard: ShardFile) -> Entry {
+        ((shard.start, shard.end), Mutex::new(shard))
+    }
+}
+
+impl Store {
+    /// Hand the shards over to a [`ConcurrentStore`].
+    ///
+    /// The store is [`sync`](Self::sync)ed first. Its write-ahead log,
+    /// observer and auto compaction stay behind.
+    pub fn into_concurrent(mut self) -> Result<ConcurrentStore> {
+        self.sync()?;
+
+        Ok(ConcurrentStore {
+            dirpath: self.dirpath.clone(),
+            opts: self.opts.clone(),
+            key_normalizer: self.key_normalizer,
+            tombstones: self.tombstones,
+            hasher: self.hasher,
+            shards: RwLock::new(
+                std::mem::take(&mut self.shards)
+                    .into_iter()
+                    .map(ConcurrentStore::entry)
+                    .collect(),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod concurrent_tests {
+    use super::*;
+    use std::thread;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_writers_on_disjoint_shards_run_alongside_a_split() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        while db.shard_ranges().len() < 4 {
+            for idx in (0..db.shard_ranges().len()).rev() {
+                db.split(idx).unwrap();
+            }
+        }
+
+        let db = db.into_concurrent().unwrap();
+        let ranges = db.shard_ranges();
+
+        // keys bucketed by the shard they land in, one writer per shard
+        let mut buckets = vec![vec![]; ranges.len()];
+        for i in 0..8000u32 {
+            let shard = PartedHash::new(&i.to_le_bytes()).shard();
+            let idx = ranges.iter().position(|&(_, end)| shard < end).unwrap();
+            buckets[idx].push(i);
+        }
+
+        thread::scope(|s| {
+            for bucket in &buckets {
+                let db = &db;
+                s.spawn(move || {
+                    for &i in bucket {
+                        db.set(&i.to_le_bytes(), &i.to_be_bytes()).unwrap();
+                    }
+                });
+            }
+
+            s.spawn(|| db.split(0).unwrap());
+        });
+
+        assert_eq!(db.shard_ranges().len(), ranges.len() + 1);
+
+        for i in 0..8000u32 {
+            assert_eq!(
+                db.get(&i.to_le_bytes()).unwrap(),
+                Some(i.to_be_bytes().to_vec())
+            );
+        }
+
+        assert!(db.remove(&0u32.to_le_bytes()).unwrap());
+        assert_eq!(db.get(&0u32.to_le_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_reads_and_writes_run_alongside_a_compaction() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        // overwrites leave dead records behind
+        for round in 0..3u32 {
+            for i in 0..5000u32 {
+                db.set(&i.to_le_bytes(), &(i + round).to_be_bytes())
+                    .unwrap();
+            }
+        }
+
+        let db = db.into_concurrent().unwrap();
+        let dead = |db: &ConcurrentStore| {
+            db.shards.read().unwrap()[0]
+                .1
+                .lock()
+                .unwrap()
+                .dead_bytes()
+                .unwrap()
+        };
+        assert_eq!(dead(&db), 2 * 5000 * 8);
+
+        thread::scope(|s| {
+            // a writer bumping keys [0, 500) while the rest stay put
+            s.spawn(|| {
+                for round in 3..10u32 {
+                    for i in 0..500u32 {
+                        db.set(&i.to_le_bytes(), &(i + round).to_be_bytes())
+                            .unwrap();
+                    }
+                }
+            });
+
+            s.spawn(|| {
+                for _ in 0..3 {
+                    for i in 0..5000u32 {
+                        let val = db.get(&i.to_le_bytes()).unwrap().unwrap();
+                        let val = u32::from_be_bytes(val.try_into().unwrap());
+
+                        match i < 500 {
+                            true => assert!((i + 2..i + 10).contains(&val)),
+                            false => assert_eq!(val, i + 2),
+                        }
+                    }
+                }
+            });
+
+            s.spawn(|| db.compact_shard(0).unwrap());
+        });
+
+        for i in 0..5000u32 {
+            let expected = if i < 500 { i + 9 } else { i + 2 };
+            assert_eq!(
+                db.get(&i.to_le_bytes()).unwrap(),
+                Some(expected.to_be_bytes().to_vec())
+            );
+        }
+
+        // the two rounds of overwrites are gone, only the writer's and rows
+        // copied twice are left
+        assert!(dead(&db) < 2 * 5000 * 8);
+
+        // the writer may outlast the compaction, leaving the header to sync
+        drop(db);
+        let mut db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.iter().count(), 5000);
+        db.compact_shard(0).unwrap();
+        assert_eq!(db.shards[0].dead_bytes().unwrap(), 0);
+    }
+}
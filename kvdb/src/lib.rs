@@ -1,21 +1,136 @@
 use std::{
-    cell::RefCell,
+    borrow::Cow,
+    cell::{Cell, UnsafeCell},
     fs::{File, OpenOptions},
-    io::{Seek, Write},
+    io::Write,
+    mem::offset_of,
+    ops::Deref,
     os::unix::fs::FileExt,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use memmap::{MmapMut, MmapOptions};
 use siphasher::sip::SipHasher24;
 
-pub type Result<T> = std::io::Result<T>;
+mod arena;
+pub use arena::{ReadArena, ValueRef};
+mod bulk;
+mod cache;
+pub use cache::CachedStore;
+mod concurrent;
+pub use concurrent::ConcurrentStore;
+mod manifest;
+pub use manifest::Manifest;
+mod membership;
+pub use membership::membership_check;
+mod single_file;
+pub use single_file::SingleFileStore;
+mod sorted;
+pub use sorted::DumpReader;
+
+#[cfg(feature = "serde")]
+pub mod typed;
+#[cfg(feature = "serde")]
+pub use typed::{Bincode, Codec, TypedStore};
+
+pub type Result<T> = std::result::Result<T, Error>;
 pub type Buf = Vec<u8>;
 pub type KV = (Buf, Buf);
+/// Maps a key to the form it's hashed and stored under, see
+/// [`Store::open_with_key_normalizer`]
+pub type KeyNormalizer = fn(&[u8]) -> Buf;
 
 const WIDTH: usize = 512;
 const ROWS: usize = 64;
 
+/// Bumped whenever the on-disk header layout changes. Unversioned headers
+/// predate the header checksum
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// The header of shard `[shard.0, shard.1)` doesn't match its checksum
+    CorruptHeader {
+        shard: (u32, u32),
+    },
+    /// [`Store::create`] found shards in `dir`
+    AlreadyExists {
+        dir: PathBuf,
+    },
+    /// [`Store::open_existing`] found no shards in `dir`
+    NotFound {
+        dir: PathBuf,
+    },
+    /// The store's [`Manifest`] describes a layout this build can't read
+    IncompatibleFormat {
+        found: Manifest,
+        expected: Manifest,
+    },
+    /// A file in the store directory is named like a shard, `{start}-{end}`,
+    /// but doesn't name a valid range
+    UnexpectedFile {
+        path: PathBuf,
+    },
+    /// The shard files in `dir` don't tile `[0, MAX_SHARD)`, leaving a gap or
+    /// a partial overlap at shard `at`
+    MisalignedShards {
+        dir: PathBuf,
+        at: u32,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::CorruptHeader {
+                shard: (start, end),
+            } => {
+                write!(f, "header of shard [{start}, {end}) is corrupt")
+            }
+            Error::AlreadyExists { dir } => {
+                write!(f, "{} already holds a store", dir.display())
+            }
+            Error::NotFound { dir } => write!(f, "no store in {}", dir.display()),
+            Error::IncompatibleFormat { found, expected } => {
+                write!(f, "store format {found:?} doesn't match {expected:?}")
+            }
+            Error::UnexpectedFile { path } => {
+                write!(f, "{} looks like a shard but isn't one", path.display())
+            }
+            Error::MisalignedShards { dir, at } => {
+                write!(
+                    f,
+                    "shards in {} have a gap or overlap at {at}",
+                    dir.display()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::CorruptHeader { .. }
+            | Error::AlreadyExists { .. }
+            | Error::NotFound { .. }
+            | Error::IncompatibleFormat { .. }
+            | Error::UnexpectedFile { .. }
+            | Error::MisalignedShards { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct PartedHash(u64);
 
@@ -26,6 +141,21 @@ impl PartedHash {
         PartedHash(SipHasher24::new().hash(buf))
     }
 
+    /// [`new`](Self::new) with XXH64 instead of SipHash, split into the same
+    /// parts. Hashes from the two can't be mixed within a store.
+    #[cfg(feature = "xxhash")]
+    pub fn new_xxhash(buf: &[u8]) -> Self {
+        PartedHash(xxhash::oneshot64(0, buf))
+    }
+
+    /// A hash with the given parts instead of one derived from a key, e.g. to
+    /// force collisions in tests. `row` must be below 64, `shard` below 65536.
+    pub const fn from_parts(sign: u32, row: usize, shard: u32) -> Self {
+        assert!(row < ROWS && shard < 1 << 16);
+
+        PartedHash(((shard as u64) << 48) | ((row as u64) << 32) | sign as u64)
+    }
+
     pub fn sign(&self) -> u32 {
         if self.0 as u32 == Self::INVALID_SIGN {
             0x12345678
@@ -43,7 +173,121 @@ impl PartedHash {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Hash function a store routes keys by, chosen when it's created with
+/// [`StoreBuilder::hasher`] and recorded in its [`Manifest`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyHasher {
+    #[default]
+    SipHash,
+    /// [`PartedHash::new_xxhash`]
+    #[cfg(feature = "xxhash")]
+    Xxh64,
+}
+
+impl KeyHasher {
+    pub fn hash(self, key: &[u8]) -> PartedHash {
+        match self {
+            Self::SipHash => PartedHash::new(key),
+            #[cfg(feature = "xxhash")]
+            Self::Xxh64 => PartedHash::new_xxhash(key),
+        }
+    }
+
+    /// How the [`Manifest`] names the hasher
+    fn name(self) -> &'static str {
+        match self {
+            Self::SipHash => "siphash",
+            #[cfg(feature = "xxhash")]
+            Self::Xxh64 => "xxh64",
+        }
+    }
+
+    /// The hasher `name`d, `None` for one unknown to this build
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "siphash" => Some(Self::SipHash),
+            #[cfg(feature = "xxhash")]
+            "xxh64" => Some(Self::Xxh64),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod failpoint {
+    use std::cell::Cell;
+
+    thread_local! {
+        static WRITES_LEFT: Cell<Option<usize>> = const { Cell::new(None) };
+        static FILE_HEADER: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Read shard headers into memory on this thread, as when mapping fails
+    pub fn force_file_header(yes: bool) {
+        FILE_HEADER.with(|f| f.set(yes));
+    }
+
+    pub fn file_header_forced() -> bool {
+        FILE_HEADER.with(|f| f.get())
+    }
+
+    /// Fail every [`ShardFile::write`](super::ShardFile::write) once `n` more writes went through
+    pub fn fail_after(n: Option<usize>) {
+        WRITES_LEFT.with(|w| w.set(n));
+    }
+
+    pub fn check() -> super::Result<()> {
+        WRITES_LEFT.with(|w| match w.get() {
+            Some(0) => Err(std::io::Error::other("injected write failure").into()),
+            Some(n) => {
+                w.set(Some(n - 1));
+                Ok(())
+            }
+            None => Ok(()),
+        })
+    }
+}
+
+/// Per-thread counts of the I/O calls tests assert on
+#[cfg(test)]
+mod counters {
+    use std::cell::Cell;
+
+    thread_local! {
+        static READS: Cell<usize> = const { Cell::new(0) };
+        static DIR_SYNCS: Cell<usize> = const { Cell::new(0) };
+        static SYNCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// No. of [`ShardFile::read`](super::ShardFile::read)s on this thread so far
+    pub fn reads() -> usize {
+        READS.with(|r| r.get())
+    }
+
+    pub fn count_read() {
+        READS.with(|r| r.set(r.get() + 1));
+    }
+
+    /// No. of [`sync_dir`](super::sync_dir) calls on this thread so far
+    pub fn dir_syncs() -> usize {
+        DIR_SYNCS.with(|d| d.get())
+    }
+
+    pub fn count_dir_sync() {
+        DIR_SYNCS.with(|d| d.set(d.get() + 1));
+    }
+
+    /// No. of [`Store::sync`](super::Store::sync) calls on this thread so far
+    pub fn syncs() -> usize {
+        SYNCS.with(|s| s.get())
+    }
+
+    pub fn count_sync() {
+        SYNCS.with(|s| s.set(s.get() + 1));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct Descriptor {
     offset: u32,
@@ -51,73 +295,493 @@ pub struct Descriptor {
     vlen: u16,
 }
 
+impl Descriptor {
+    /// High bit of `klen`, reserved to mark a record holding only a suffix of
+    /// its value, with the rest in an earlier fragment. Never set yet, see
+    /// [`Store::append`].
+    ///
+    /// A chained record never has an empty suffix, so [`CHAINED`](Self::CHAINED)
+    /// with a zero `vlen` marks a tombstone instead: the record of a removed
+    /// key, holding the key alone, see [`StoreBuilder::tombstones`].
+    const CHAINED: u16 = 1 << 15;
+    const MAX_KEY_LEN: usize = Self::CHAINED as usize - 1;
+    const MAX_VAL_LEN: usize = u16::MAX as usize;
+
+    fn check_lens(k: &[u8], v: &[u8]) -> Result<()> {
+        if k.len() > Self::MAX_KEY_LEN || v.len() > Self::MAX_VAL_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} byte key or {} byte value over the {} / {} byte limit",
+                    k.len(),
+                    v.len(),
+                    Self::MAX_KEY_LEN,
+                    Self::MAX_VAL_LEN
+                ),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Bytes the record takes in the data region
+    fn len(&self) -> u64 {
+        self.klen() as u64 + self.vlen as u64
+    }
+
+    /// Length of the key, without the flag bit
+    fn klen(&self) -> usize {
+        (self.klen & !Self::CHAINED) as usize
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.klen & Self::CHAINED != 0 && self.vlen == 0
+    }
+
+    /// Mark the record, written with an empty value, as a tombstone
+    fn set_tombstone(&mut self) {
+        debug_assert_eq!(self.vlen, 0);
+        self.klen |= Self::CHAINED;
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct ShardRow {
     signs: [u32; WIDTH],
     descriptors: [Descriptor; WIDTH],
 }
 
+/// Signs compared per step of [`ShardRow::slots_with`]
+const SIGN_LANES: usize = 8;
+
+impl ShardRow {
+    /// Slots holding `sign`, in order, comparing 8 signs per AVX2 instruction
+    /// when available
+    fn slots_with(&self, sign: u32) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        #[cfg(target_arch = "x86_64")]
+        let avx2 = is_x86_feature_detected!("avx2");
+
+        self.signs
+            .chunks_exact(SIGN_LANES)
+            .enumerate()
+            .flat_map(move |(block, signs)| {
+                #[cfg(target_arch = "x86_64")]
+                let mask = match avx2 {
+                    true => unsafe { sign_mask_avx2(signs, sign) },
+                    false => sign_mask(signs, sign),
+                };
+                #[cfg(not(target_arch = "x86_64"))]
+                let mask = sign_mask(signs, sign);
+
+                MaskSlots {
+                    mask,
+                    base: block * SIGN_LANES,
+                }
+            })
+    }
+}
+
+/// Slots of the bits set in a [`sign_mask`], `base` being the slot of bit 0
+struct MaskSlots {
+    mask: u32,
+    base: usize,
+}
+
+impl Iterator for MaskSlots {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.mask == 0 {
+            return None;
+        }
+
+        let i = self.mask.trailing_zeros() as usize;
+        self.mask &= self.mask - 1;
+        Some(self.base + i)
+    }
+}
+
+impl DoubleEndedIterator for MaskSlots {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.mask == 0 {
+            return None;
+        }
+
+        let i = 31 - self.mask.leading_zeros() as usize;
+        self.mask &= !(1 << i);
+        Some(self.base + i)
+    }
+}
+
+/// Bit `i` is set if `signs[i] == sign`
+fn sign_mask(signs: &[u32], sign: u32) -> u32 {
+    signs
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (i, &s)| mask | ((s == sign) as u32) << i)
+}
+
+/// [`sign_mask`] of `SIGN_LANES` signs at once
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn sign_mask_avx2(signs: &[u32], sign: u32) -> u32 {
+    use std::arch::x86_64::*;
+
+    debug_assert_eq!(signs.len(), SIGN_LANES);
+
+    let lanes = _mm256_loadu_si256(signs.as_ptr() as *const __m256i);
+    let eq = _mm256_cmpeq_epi32(lanes, _mm256_set1_epi32(sign as i32));
+
+    _mm256_movemask_ps(_mm256_castsi256_ps(eq)) as u32
+}
+
 #[repr(C)]
 pub struct ShardHeader {
     rows: [ShardRow; ROWS],
+    version: u32,
+    /// Bytes of the header region once grown by [`ShardFile::remap`], 0 for
+    /// just the `ShardHeader`
+    header_len: u32,
+    /// Over `rows`, refreshed by [`ShardFile::sync`]
+    checksum: u64,
+}
+
+/// Persist the entries of `dirpath`, so a shard file created, renamed or
+/// removed in it is still (or no longer) there after a crash
+fn sync_dir(dirpath: &Path) -> Result<()> {
+    #[cfg(test)]
+    counters::count_dir_sync();
+
+    File::open(dirpath)?.sync_all()?;
+
+    Ok(())
+}
+
+/// The bytes of a shard's header: mapped from the file, or, where the file
+/// can't be mapped (some network filesystems and sandboxes), read into
+/// memory and written back on [`ShardFile::sync`]
+enum HeaderBuf {
+    Mapped(MmapMut),
+    /// `len` bytes, in `u64`s to keep the [`ShardHeader`] aligned
+    Owned {
+        buf: UnsafeCell<Vec<u64>>,
+        len: usize,
+    },
+}
+
+impl HeaderBuf {
+    /// Map the first `len` bytes of `file`, falling back to reading them
+    fn new(file: &File, len: usize) -> Result<Self> {
+        #[cfg(test)]
+        let mapped = match failpoint::file_header_forced() {
+            true => Err(std::io::Error::other("file header forced")),
+            false => unsafe { MmapOptions::new().len(len).map_mut(file) },
+        };
+        #[cfg(not(test))]
+        let mapped = unsafe { MmapOptions::new().len(len).map_mut(file) };
+
+        if let Ok(mmap) = mapped {
+            return Ok(Self::Mapped(mmap));
+        }
+
+        let mut buf = vec![0u64; len.div_ceil(size_of::<u64>())];
+        let bytes = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, len) };
+        file.read_exact_at(bytes, 0)?;
+
+        Ok(Self::Owned {
+            buf: UnsafeCell::new(buf),
+            len,
+        })
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        match self {
+            Self::Mapped(mmap) => mmap.as_ptr() as *mut u8,
+            Self::Owned { buf, .. } => unsafe { (*buf.get()).as_mut_ptr() as *mut u8 },
+        }
+    }
+
+    /// Persist the header to `file`
+    fn flush(&self, file: &File) -> Result<()> {
+        match self {
+            Self::Mapped(mmap) => mmap.flush()?,
+            Self::Owned { .. } => {
+                file.write_all_at(self, 0)?;
+                file.sync_data()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Deref for HeaderBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let len = match self {
+            Self::Mapped(mmap) => mmap.len(),
+            Self::Owned { len, .. } => *len,
+        };
+
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), len) }
+    }
 }
 
 pub struct ShardFile {
     start: u32,
     end: u32,
-    file: RefCell<File>,
-    mmap: MmapMut,
+    file: File,
+    /// End of the data region, where the next record is written
+    write_pos: Cell<u64>,
+    header_buf: HeaderBuf,
+    /// Bytes of the records live slots point at, see
+    /// [`space_amplification`](Self::space_amplification)
+    live_bytes: Cell<u64>,
 }
 
 impl ShardFile {
     const HEADER_SIZE: u64 = size_of::<ShardHeader>() as u64;
+    const ROWS_SIZE: usize = size_of::<[ShardRow; ROWS]>();
 
-    pub fn open(dirpath: impl AsRef<Path>, start: u32, end: u32) -> Result<Self> {
-        let filepath = dirpath.as_ref().join(format!("{start}-{end}"));
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(filepath)?;
+    /// Create an empty shard, replacing any file already covering the range
+    pub fn create(
+        opts: &OpenOptions,
+        dirpath: impl AsRef<Path>,
+        start: u32,
+        end: u32,
+    ) -> Result<Self> {
+        Self::create_at(
+            opts,
+            dirpath.as_ref().join(format!("{start}-{end}")),
+            start,
+            end,
+        )
+    }
+
+    /// Suffix of shard and manifest files still being written, never the
+    /// source of truth: [`StoreBuilder::open`] deletes them
+    const TMP_SUFFIX: &'static str = ".tmp";
+
+    /// `(start, end)` of the shard file `name`, `None` for a file outside the
+    /// `{start}-{end}` scheme. Digits on both sides of the dash that don't
+    /// spell a valid range (`5-3`, `0-70000`, `00-9`) are an
+    /// [`Error::UnexpectedFile`], as that's a damaged or foreign shard rather
+    /// than a stray file.
+    fn parse_name(dirpath: &Path, name: &str) -> Result<Option<(u32, u32)>> {
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+        let Some((start, end)) = name.split_once('-') else {
+            return Ok(None);
+        };
+        if !digits(start) || !digits(end) {
+            return Ok(None);
+        }
+
+        match (start.parse::<u32>(), end.parse::<u32>()) {
+            (Ok(start), Ok(end))
+                if start < end && end <= Store::MAX_SHARD && name == format!("{start}-{end}") =>
+            {
+                Ok(Some((start, end)))
+            }
+            _ => Err(Error::UnexpectedFile {
+                path: dirpath.join(name),
+            }),
+        }
+    }
+
+    /// `{start}-{end}.tmp`, the name a shard is written under before being
+    /// renamed into place
+    fn tmp_name(start: u32, end: u32) -> String {
+        format!("{start}-{end}{}", Self::TMP_SUFFIX)
+    }
+
+    /// [`create`](Self::create) under another file name, to be renamed into
+    /// place once complete
+    fn create_at(opts: &OpenOptions, filepath: PathBuf, start: u32, end: u32) -> Result<Self> {
+        let file = opts.clone().create(true).truncate(true).open(&filepath)?;
 
         file.set_len(Self::HEADER_SIZE)?;
-        file.seek(std::io::SeekFrom::End(0))?;
-        let mmap = unsafe {
-            MmapOptions::new()
-                .len(Self::HEADER_SIZE as usize)
-                .map_mut(&file)
-        }?;
-
-        Ok(Self {
+        let shard = Self::map(file, start, end)?;
+
+        shard.header().version = FORMAT_VERSION;
+        shard.sync()?;
+        sync_dir(filepath.parent().unwrap())?;
+
+        Ok(shard)
+    }
+
+    /// Open an existing shard, checking its header against the stored checksum
+    pub fn open(
+        opts: &OpenOptions,
+        dirpath: impl AsRef<Path>,
+        start: u32,
+        end: u32,
+    ) -> Result<Self> {
+        Self::open_checked(opts, dirpath, start, end, true)
+    }
+
+    /// [`open`](Self::open), skipping the checksum unless `verify`: a crash
+    /// leaves the header with changes the checksum doesn't cover yet, to be
+    /// made whole by replaying the write-ahead log
+    fn open_checked(
+        opts: &OpenOptions,
+        dirpath: impl AsRef<Path>,
+        start: u32,
+        end: u32,
+        verify: bool,
+    ) -> Result<Self> {
+        let filepath = dirpath.as_ref().join(format!("{start}-{end}"));
+        let file = opts.open(filepath)?;
+        let shard = Self::map(file, start, end)?;
+
+        let header = shard.header();
+
+        if header.version != FORMAT_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "shard [{start}, {end}) has format version {}, expected {FORMAT_VERSION}",
+                    header.version
+                ),
+            )
+            .into());
+        }
+
+        if verify && header.checksum != shard.checksum() {
+            return Err(Error::CorruptHeader {
+                shard: (start, end),
+            });
+        }
+
+        Ok(shard)
+    }
+
+    /// Map the header of an already sized shard file, or read it into memory
+    /// when it can't be mapped.
+    ///
+    /// [`header_row`](Self::header_row) relies on the header covering a whole
+    /// `ShardHeader`, so a file too short to hold one, or the header region
+    /// recorded in it, is rejected here.
+    fn map(file: File, start: u32, end: u32) -> Result<Self> {
+        let file_len = file.metadata()?.len();
+
+        let mut header_len = Self::HEADER_SIZE;
+        if file_len >= Self::HEADER_SIZE {
+            let mut buf = [0; 4];
+            file.read_exact_at(&mut buf, offset_of!(ShardHeader, header_len) as u64)?;
+            header_len = header_len.max(u32::from_ne_bytes(buf) as u64);
+        }
+
+        if file_len < header_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "shard [{start}, {end}) is {file_len} bytes, shorter than its {header_len} byte header",
+                ),
+            )
+            .into());
+        }
+
+        let header_buf = HeaderBuf::new(&file, header_len as usize)?;
+
+        let shard = Self {
             start,
             end,
-            file: RefCell::new(file),
-            mmap,
-        })
+            file,
+            write_pos: Cell::new(file_len),
+            header_buf,
+            live_bytes: Cell::new(0),
+        };
+
+        let live_bytes = (0..ROWS)
+            .flat_map(|r| {
+                let row = shard.header_row(r);
+                row.signs
+                    .iter()
+                    .zip(row.descriptors.iter())
+                    .filter(|(&s, _)| s != PartedHash::INVALID_SIGN)
+                    .map(|(_, desc)| desc.len())
+            })
+            .sum();
+        shard.live_bytes.set(live_bytes);
+
+        Ok(shard)
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn header(&self) -> &mut ShardHeader {
+        debug_assert!(self.header_buf.len() >= Self::HEADER_SIZE as usize);
+
+        unsafe { &mut *(self.header_buf.as_ptr() as *mut ShardHeader) }
     }
 
+    #[allow(clippy::mut_from_ref)]
     pub fn header_row(&self, r: usize) -> &mut ShardRow {
-        &mut unsafe { &mut *(self.mmap.as_ptr() as *const ShardHeader as *mut ShardHeader) }.rows[r]
+        &mut self.header().rows[r]
+    }
+
+    fn checksum(&self) -> u64 {
+        SipHasher24::new().hash(&self.header_buf[..Self::ROWS_SIZE])
+    }
+
+    /// Refresh the header checksum and flush the header and data to disk
+    pub fn sync(&self) -> Result<()> {
+        self.header().checksum = self.checksum();
+        self.file.sync_data()?;
+        self.header_buf.flush(&self.file)?;
+
+        Ok(())
+    }
+
+    /// The key of the record at `desc`, leaving its value on disk
+    pub fn read_key(&self, desc: Descriptor) -> Result<Buf> {
+        #[cfg(test)]
+        counters::count_read();
+
+        let mut k = vec![0; desc.klen()];
+        self.file.read_exact_at(&mut k, desc.offset as u64)?;
+
+        Ok(k)
     }
 
     pub fn read(&self, desc: Descriptor) -> Result<KV> {
-        let mut k = vec![0u8; desc.klen as usize];
-        let mut v = vec![0u8; desc.vlen as usize];
-        let f = self.file.borrow();
+        let mut k = Vec::new();
+        let mut v = Vec::new();
 
-        f.read_exact_at(&mut k, desc.offset as u64)?;
-        f.read_exact_at(&mut v, desc.offset as u64 + desc.klen as u64)?;
+        self.read_into(desc, &mut k, &mut v)?;
 
         Ok((k, v))
     }
 
+    /// [`read`](Self::read) into caller provided buffers, resized to fit
+    pub fn read_into(&self, desc: Descriptor, k: &mut Buf, v: &mut Buf) -> Result<()> {
+        #[cfg(test)]
+        counters::count_read();
+
+        k.resize(desc.klen(), 0);
+        v.resize(desc.vlen as usize, 0);
+
+        self.file.read_exact_at(k, desc.offset as u64)?;
+        self.file
+            .read_exact_at(v, desc.offset as u64 + desc.klen() as u64)?;
+
+        Ok(())
+    }
+
     pub fn write(&self, k: &[u8], v: &[u8]) -> Result<Descriptor> {
-        let mut f = self.file.borrow_mut();
-        let offset = f.stream_position()?;
+        #[cfg(test)]
+        failpoint::check()?;
+
+        Descriptor::check_lens(k, v)?;
+
+        let offset = self.write_pos.get();
 
-        f.write_all(k)?;
-        f.write_all(v)?;
+        self.file.write_all_at(k, offset)?;
+        self.file.write_all_at(v, offset + k.len() as u64)?;
+        self.write_pos.set(offset + k.len() as u64 + v.len() as u64);
 
         Ok(Descriptor {
             offset: offset as u32,
@@ -127,173 +791,3310 @@ impl ShardFile {
     }
 
     pub fn get(&self, ph: PartedHash, key: &[u8]) -> Result<Option<Buf>> {
-        let row = self.header_row(ph.row());
+        match self.lookup(ph, key)? {
+            GetResult::Found(v) => Ok(Some(v)),
+            GetResult::Deleted | GetResult::Missing => Ok(None),
+        }
+    }
 
-        for (i, s) in row.signs.iter().enumerate() {
-            if *s == ph.sign() {
-                let desc = row.descriptors[i];
-                let (k, v) = self.read(desc)?;
+    /// [`get`](Self::get) telling a tombstoned key from a missing one
+    pub fn lookup(&self, ph: PartedHash, key: &[u8]) -> Result<GetResult> {
+        let Some((i, v)) = self.find(ph, key)? else {
+            return Ok(GetResult::Missing);
+        };
 
-                if k == key {
-                    return Ok(Some(v));
-                }
-            }
+        if self.header_row(ph.row()).descriptors[i].is_tombstone() {
+            return Ok(GetResult::Deleted);
         }
 
-        Ok(None)
+        Ok(GetResult::Found(v))
     }
 
-    pub fn set(&self, ph: PartedHash, key: &[u8], val: &[u8]) -> Result<bool> {
+    /// [`find`](Self::find) passing over a tombstone of `key`
+    fn find_live(&self, ph: PartedHash, key: &[u8]) -> Result<Option<(usize, Buf)>> {
         let row = self.header_row(ph.row());
+        Ok(self
+            .find(ph, key)?
+            .filter(|&(i, _)| !row.descriptors[i].is_tombstone()))
+    }
 
-        for (i, s) in row.signs.iter().enumerate() {
-            if *s == ph.sign() {
-                let desc = row.descriptors[i];
-                let (k, _) = self.read(desc)?;
+    /// Slot of `key` within its row, along with its value, a tombstone's
+    /// being empty
+    fn find(&self, ph: PartedHash, key: &[u8]) -> Result<Option<(usize, Buf)>> {
+        let row = self.header_row(ph.row());
 
-                if k == key {
-                    row.descriptors[i] = self.write(key, val)?;
-                    return Ok(true);
-                }
-            }
-        }
+        for i in row.slots_with(ph.sign()).rev() {
+            let (k, v) = self.read(row.descriptors[i])?;
 
-        for (i, s) in row.signs.iter_mut().enumerate() {
-            if *s == PartedHash::INVALID_SIGN {
-                *s = ph.sign();
-                row.descriptors[i] = self.write(key, val)?;
-                return Ok(true);
+            if k == key {
+                return Ok(Some((i, v)));
             }
         }
 
-        Ok(false)
+        Ok(None)
     }
 
-    pub fn remove(&mut self, ph: PartedHash, key: &[u8]) -> Result<bool> {
+    pub fn get_into(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        key_buf: &mut Buf,
+        val_buf: &mut Buf,
+    ) -> Result<bool> {
         let row = self.header_row(ph.row());
 
-        for (i, s) in row.signs.iter_mut().enumerate() {
-            if *s == ph.sign() {
-                let desc = row.descriptors[i];
-                let (k, _) = self.read(desc)?;
+        for i in row.slots_with(ph.sign()).rev() {
+            self.read_into(row.descriptors[i], key_buf, val_buf)?;
 
-                if k == key {
-                    *s = PartedHash::INVALID_SIGN;
-                    return Ok(true);
-                }
+            if key_buf == key {
+                return Ok(!row.descriptors[i].is_tombstone());
             }
         }
 
         Ok(false)
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Result<KV>> + 'a {
-        (0..ROWS).map(|r| self.header_row(r)).flat_map(|row| {
-            row.signs.iter().enumerate().filter_map(|(i, sig)| {
-                if *sig == PartedHash::INVALID_SIGN {
-                    return None;
-                }
-                Some(self.read(row.descriptors[i]))
-            })
-        })
+    pub fn set(&self, ph: PartedHash, key: &[u8], val: &[u8]) -> Result<bool> {
+        Ok(self.set_at(ph, key, val)?.is_some())
     }
-}
-
-pub struct Store {
-    dirpath: PathBuf,
-    shards: Vec<ShardFile>,
-}
 
-impl Store {
-    const MAX_SHARD: u32 = u16::MAX as u32 + 1;
+    /// Like [`set`](Self::set), but returns the slot the entry landed in and
+    /// the value it replaced, `None` if the row is full
+    pub fn set_at(
+        &self,
+        ph: PartedHash,
+        key: &[u8],
+        val: &[u8],
+    ) -> Result<Option<(usize, Option<Buf>)>> {
+        if let Some((i, v)) = self.find(ph, key)? {
+            // a tombstone's slot is reused, but the key had no value
+            let slot = &mut self.header_row(ph.row()).descriptors[i];
+            let prev = (!slot.is_tombstone()).then_some(v);
 
-    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
-        let dirpath = dir.as_ref().to_path_buf();
-        std::fs::create_dir_all(&dirpath)?;
-        let first_shard = ShardFile::open(&dirpath, 0, Self::MAX_SHARD)?;
+            let desc = self.write(key, val)?;
+            self.point(slot, desc);
+            return Ok(Some((i, prev)));
+        }
 
-        Ok(Store {
-            dirpath,
-            shards: vec![first_shard],
-        })
+        Ok(self.insert(ph, key, val)?.map(|i| (i, None)))
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Option<Buf>> {
-        let ph = PartedHash::new(key);
+    /// Insert into the first free slot of the row without looking for an
+    /// existing entry of the same key, `false` if the row is full.
+    ///
+    /// Lookups scan the slots holding a sign from the last one, so the entry
+    /// trades places with the last of them: on a duplicate key, the latest
+    /// write wins.
+    pub fn set_blind(&self, ph: PartedHash, key: &[u8], val: &[u8]) -> Result<bool> {
+        let Some(i) = self.insert(ph, key, val)? else {
+            return Ok(false);
+        };
 
-        for shard in self.shards.iter() {
-            if ph.shard() < shard.end {
-                return shard.get(ph, key);
-            }
-        }
+        let row = self.header_row(ph.row());
+        let last = row.slots_with(ph.sign()).next_back().unwrap();
+        row.descriptors.swap(i, last);
 
-        unreachable!()
+        Ok(true)
     }
 
-    pub fn remove(&mut self, key: &[u8]) -> Result<bool> {
-        let ph = PartedHash::new(key);
+    /// Write the entry into the first free slot of its row, returning the
+    /// slot, `None` if the row is full
+    fn insert(&self, ph: PartedHash, key: &[u8], val: &[u8]) -> Result<Option<usize>> {
+        let row = self.header_row(ph.row());
 
-        for shard in self.shards.iter_mut() {
-            if ph.shard() < shard.end {
-                return shard.remove(ph, key);
-            }
-        }
+        let Some(i) = row.slots_with(PartedHash::INVALID_SIGN).next() else {
+            return Ok(None);
+        };
+
+        row.descriptors[i] = self.write(key, val)?;
+        row.signs[i] = ph.sign();
+        self.live_bytes
+            .set(self.live_bytes.get() + row.descriptors[i].len());
 
-        unreachable!()
+        Ok(Some(i))
     }
 
-    pub fn split(&mut self, shard_idx: usize) -> Result<()> {
-        let removed_shard = self.shards.remove(shard_idx);
+    /// [`insert`](Self::insert) a tombstone of `key`, `false` if the row is
+    /// full
+    fn insert_tombstone(&self, ph: PartedHash, key: &[u8]) -> Result<bool> {
+        let Some(i) = self.insert(ph, key, &[])? else {
+            return Ok(false);
+        };
 
-        let start = removed_shard.start;
-        let end = removed_shard.end;
-        let mid = (start + end) / 2;
-        println!("splitting [{start}, {end}) to [{start}, {mid}) and [{mid}, {end})");
+        self.header_row(ph.row()).descriptors[i].set_tombstone();
+        Ok(true)
+    }
 
-        let top = ShardFile::open(&self.dirpath, start, mid)?;
-        let bottom = ShardFile::open(&self.dirpath, mid, end)?;
+    pub fn remove(&mut self, ph: PartedHash, key: &[u8]) -> Result<bool> {
+        let Some((i, _)) = self.find(ph, key)? else {
+            return Ok(false);
+        };
 
-        for res in removed_shard.iter() {
-            let (key, val) = res?;
-            let ph = PartedHash::new(&key);
+        let row = self.header_row(ph.row());
+        row.signs[i] = PartedHash::INVALID_SIGN;
+        self.live_bytes
+            .set(self.live_bytes.get() - row.descriptors[i].len());
 
-            if ph.shard() < mid {
-                bottom.set(ph, &key, &val)?;
-            } else {
-                top.set(ph, &key, &val)?;
+        Ok(!row.descriptors[i].is_tombstone())
+    }
+
+    /// [`remove`](Self::remove) leaving a tombstone in the key's slot, so
+    /// [`lookup`](Self::lookup) reports it [`GetResult::Deleted`] until the
+    /// shard is compacted. `false` if the key is missing or already deleted.
+    pub fn tombstone(&self, ph: PartedHash, key: &[u8]) -> Result<bool> {
+        let Some((i, _)) = self.find(ph, key)? else {
+            return Ok(false);
+        };
+
+        let slot = &mut self.header_row(ph.row()).descriptors[i];
+        if slot.is_tombstone() {
+            return Ok(false);
+        }
+
+        let mut desc = self.write(key, &[])?;
+        desc.set_tombstone();
+        self.point(slot, desc);
+
+        Ok(true)
+    }
+
+    /// Point the descriptor of an occupied slot at a newly written record,
+    /// leaving the one it pointed at dead
+    fn point(&self, slot: &mut Descriptor, desc: Descriptor) {
+        let live_bytes = self.live_bytes.get() - slot.len() + desc.len();
+
+        *slot = desc;
+        self.live_bytes.set(live_bytes);
+    }
+
+    /// Write `records` back to back at the end of the data region in a
+    /// single write, without touching the header. Pair with
+    /// [`rebuild_index`](Self::rebuild_index) to bulk load a shard, e.g. when
+    /// migrating between layouts.
+    pub fn append_raw(&self, records: &[(&[u8], &[u8])]) -> Result<Vec<Descriptor>> {
+        #[cfg(test)]
+        failpoint::check()?;
+
+        let mut buf = Vec::with_capacity(records.iter().map(|(k, v)| k.len() + v.len()).sum());
+        let mut descs = Vec::with_capacity(records.len());
+        let start = self.write_pos.get();
+
+        for (k, v) in records {
+            Descriptor::check_lens(k, v)?;
+
+            descs.push(Descriptor {
+                offset: (start + buf.len() as u64) as u32,
+                klen: k.len() as u16,
+                vlen: v.len() as u16,
+            });
+            buf.extend_from_slice(k);
+            buf.extend_from_slice(v);
+        }
+
+        self.file.write_all_at(&buf, start)?;
+        self.write_pos.set(start + buf.len() as u64);
+
+        Ok(descs)
+    }
+
+    /// Replace the whole header index with `entries`, each key placed in the
+    /// first free slot of its row in order. Nothing changes if a key is
+    /// outside the shard's range or a row would overflow.
+    ///
+    /// Keys aren't checked against the records the descriptors point at, nor
+    /// for duplicates.
+    pub fn rebuild_index(&self, hasher: KeyHasher, entries: &[(&[u8], Descriptor)]) -> Result<()> {
+        let mut row_lens = vec![0usize; ROWS];
+        let mut placed = Vec::with_capacity(entries.len());
+
+        for &(key, desc) in entries {
+            let ph = hasher.hash(key);
+
+            if !(self.start..self.end).contains(&ph.shard()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("key outside shard {}-{}", self.start, self.end),
+                )
+                .into());
             }
+
+            let slot = row_lens[ph.row()];
+            if slot == WIDTH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("over {WIDTH} keys in row {}", ph.row()),
+                )
+                .into());
+            }
+
+            row_lens[ph.row()] += 1;
+            placed.push((ph, slot, desc));
+        }
+
+        for r in 0..ROWS {
+            self.header_row(r).signs.fill(PartedHash::INVALID_SIGN);
         }
 
-        std::fs::remove_file(self.dirpath.join(format!("{start}-{end}")))?;
+        for &(ph, slot, desc) in placed.iter() {
+            let row = self.header_row(ph.row());
+            row.signs[slot] = ph.sign();
+            row.descriptors[slot] = desc;
+        }
 
-        self.shards.push(bottom);
-        self.shards.push(top);
-        self.shards.sort_by(|x, y| x.end.cmp(&y.end));
+        self.live_bytes
+            .set(placed.iter().map(|(_, _, desc)| desc.len()).sum());
 
         Ok(())
     }
 
-    pub fn set(&mut self, key: &[u8], val: &[u8]) -> Result<bool> {
-        let ph = PartedHash::new(key);
+    /// Make row `r` hold the live entries of row `r` of `from`, each in the
+    /// same slot, with the records rewritten into this shard. Tombstones are
+    /// left behind. Returns the row of `from` as copied.
+    fn copy_row(&self, from: &ShardFile, r: usize) -> Result<ShardRow> {
+        let src = from.header_row(r).clone();
+        let dst = self.header_row(r);
 
-        loop {
-            let mut shard_to_split = None;
+        for i in 0..WIDTH {
+            if dst.signs[i] != PartedHash::INVALID_SIGN {
+                self.live_bytes
+                    .set(self.live_bytes.get() - dst.descriptors[i].len());
+                dst.signs[i] = PartedHash::INVALID_SIGN;
+            }
 
-            for (i, shard) in self.shards.iter_mut().enumerate() {
-                if ph.shard() < shard.end {
-                    if shard.set(ph, key, val)? {
-                        return Ok(true);
-                    }
-                    shard_to_split = Some(i);
-                    break;
+            if src.signs[i] != PartedHash::INVALID_SIGN && !src.descriptors[i].is_tombstone() {
+                let (k, v) = from.read(src.descriptors[i])?;
+
+                dst.descriptors[i] = self.write(&k, &v)?;
+                dst.signs[i] = src.signs[i];
+                self.live_bytes
+                    .set(self.live_bytes.get() + dst.descriptors[i].len());
+            }
+        }
+
+        Ok(src)
+    }
+
+    /// Where the data region begins, right after the header
+    fn data_start(&self) -> u64 {
+        self.header_buf.len() as u64
+    }
+
+    /// Grow the header region to `header_len` bytes, for migrating to a
+    /// layout with a larger header. The shard is rewritten under a `.tmp`
+    /// name with the data region moved up to make room and live descriptors
+    /// pointed at the moved records, then renamed into place, so a crash
+    /// midway leaves the shard as it was. The bytes
+    /// past the current [`ShardHeader`] come out zeroed.
+    pub fn remap(&mut self, opts: &OpenOptions, dirpath: &Path, header_len: u64) -> Result<()> {
+        let old_len = self.data_start();
+
+        if header_len < old_len || header_len > u32::MAX as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("can't resize the {old_len} byte header to {header_len} bytes"),
+            )
+            .into());
+        }
+
+        let delta = header_len - old_len;
+        let tmp_path = dirpath.join(Self::tmp_name(self.start, self.end));
+        let file = opts.clone().create(true).truncate(true).open(&tmp_path)?;
+
+        let mut header = vec![0; header_len as usize];
+        header[..old_len as usize].copy_from_slice(&self.header_buf);
+        header[offset_of!(ShardHeader, header_len)..][..4]
+            .copy_from_slice(&(header_len as u32).to_ne_bytes());
+        file.write_all_at(&header, 0)?;
+
+        let mut data = vec![0; (self.write_pos.get() - old_len) as usize];
+        self.file.read_exact_at(&mut data, old_len)?;
+        file.write_all_at(&data, header_len)?;
+
+        let shard = Self::map(file, self.start, self.end)?;
+        for r in 0..ROWS {
+            let row = shard.header_row(r);
+
+            for (&s, desc) in row.signs.iter().zip(row.descriptors.iter_mut()) {
+                if s != PartedHash::INVALID_SIGN {
+                    desc.offset += delta as u32;
+                }
+            }
+        }
+        shard.sync()?;
+
+        std::fs::rename(
+            &tmp_path,
+            dirpath.join(format!("{}-{}", self.start, self.end)),
+        )?;
+        sync_dir(dirpath)?;
+        *self = shard;
+
+        Ok(())
+    }
+
+    /// Bytes in the data region no live slot points at
+    pub fn dead_bytes(&self) -> Result<u64> {
+        let data_len = self.write_pos.get() - self.data_start();
+        Ok(data_len - self.live_bytes.get())
+    }
+
+    /// Size of the data region relative to the live records in it, 1.0 when
+    /// nothing is dead. Infinite once every record is dead.
+    pub fn space_amplification(&self) -> Result<f64> {
+        let dead = self.dead_bytes()?;
+        let live = self.live_bytes.get();
+
+        if dead == 0 {
+            return Ok(1.0);
+        }
+
+        Ok((live + dead) as f64 / live as f64)
+    }
+
+    /// Cut off the dead tail of the data region, past the last byte any live
+    /// slot points at. Returns the no. of bytes reclaimed.
+    pub fn truncate_to_live(&self) -> Result<u64> {
+        let live_end = (0..ROWS)
+            .flat_map(|r| {
+                let row = self.header_row(r);
+                row.signs
+                    .iter()
+                    .zip(row.descriptors.iter())
+                    .filter(|(&s, _)| s != PartedHash::INVALID_SIGN)
+                    .map(|(_, desc)| desc.offset as u64 + desc.len())
+            })
+            .max()
+            .unwrap_or(self.data_start());
+
+        let end = self.write_pos.get();
+
+        if live_end >= end {
+            return Ok(0);
+        }
+
+        self.file.set_len(live_end)?;
+        self.write_pos.set(live_end);
+
+        Ok(end - live_end)
+    }
+
+    /// [`ShardStats`] of this shard, from the header alone
+    pub fn stats(&self) -> Result<ShardStats> {
+        let runs: Vec<usize> = (0..ROWS)
+            .map(|r| {
+                let (mut run, mut longest) = (0, 0);
+
+                for &s in self.header_row(r).signs.iter() {
+                    run = if s == PartedHash::INVALID_SIGN {
+                        0
+                    } else {
+                        run + 1
+                    };
+                    longest = longest.max(run);
+                }
+
+                longest
+            })
+            .collect();
+
+        Ok(ShardStats {
+            range: (self.start, self.end),
+            live_slots: self.live_slots(),
+            dead_bytes: self.dead_bytes()?,
+            avg_occupied_run: runs.iter().sum::<usize>() as f64 / ROWS as f64,
+            max_run: runs.into_iter().max().unwrap_or(0),
+        })
+    }
+
+    /// No. of occupied slots, from the header alone
+    pub fn live_slots(&self) -> usize {
+        (0..ROWS)
+            .map(|r| {
+                self.header_row(r)
+                    .signs
+                    .iter()
+                    .filter(|&&s| s != PartedHash::INVALID_SIGN)
+                    .count()
+            })
+            .sum()
+    }
+
+    pub fn for_each_mut<F: FnMut(&[u8], &mut Buf) -> bool>(&mut self, f: &mut F) -> Result<()> {
+        let mut k = Buf::new();
+        let mut v = Buf::new();
+
+        for r in 0..ROWS {
+            let row = self.header_row(r);
+
+            for (i, &s) in row.signs.iter().enumerate() {
+                if s == PartedHash::INVALID_SIGN || row.descriptors[i].is_tombstone() {
+                    continue;
+                }
+
+                self.read_into(row.descriptors[i], &mut k, &mut v)?;
+
+                if f(&k, &mut v) {
+                    let desc = self.write(&k, &v)?;
+                    self.point(&mut row.descriptors[i], desc);
                 }
             }
+        }
+
+        Ok(())
+    }
+
+    pub fn retain<F: FnMut(&[u8], &[u8]) -> bool>(&mut self, f: &mut F) -> Result<()> {
+        let mut k = Buf::new();
+        let mut v = Buf::new();
+
+        for r in 0..ROWS {
+            let row = self.header_row(r);
+
+            for (i, s) in row.signs.iter_mut().enumerate() {
+                if *s == PartedHash::INVALID_SIGN || row.descriptors[i].is_tombstone() {
+                    continue;
+                }
+
+                self.read_into(row.descriptors[i], &mut k, &mut v)?;
 
-            self.split(shard_to_split.unwrap())?;
+                if !f(&k, &v) {
+                    *s = PartedHash::INVALID_SIGN;
+                    self.live_bytes
+                        .set(self.live_bytes.get() - row.descriptors[i].len());
+                }
+            }
         }
+
+        Ok(())
     }
 
     pub fn iter<'a>(&'a self) -> impl Iterator<Item = Result<KV>> + 'a {
-        self.shards.iter().flat_map(|shard| shard.iter())
+        self.iter_with_tombstones().filter_map(|res| match res {
+            Ok((_, true)) => None,
+            res => Some(res.map(|(kv, _)| kv)),
+        })
+    }
+
+    /// [`iter`](Self::iter) including the tombstones, flagged `true`, to carry
+    /// them over to other shards
+    fn iter_with_tombstones<'a>(&'a self) -> impl Iterator<Item = Result<(KV, bool)>> + 'a {
+        (0..ROWS).flat_map(|r| {
+            self.occupied(r)
+                .into_iter()
+                .map(|desc| self.read(desc).map(|kv| (kv, desc.is_tombstone())))
+        })
+    }
+
+    /// [`iter`](Self::iter) over the keys alone
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = Result<Buf>> + 'a {
+        (0..ROWS).flat_map(|r| {
+            self.occupied(r)
+                .into_iter()
+                .filter(|desc| !desc.is_tombstone())
+                .map(|desc| self.read_key(desc))
+        })
+    }
+
+    /// Descriptors of the occupied slots of row `r`, copied out so no
+    /// reference into the header outlives the call while records are read
+    fn occupied(&self, r: usize) -> Vec<Descriptor> {
+        let row = self.header_row(r);
+
+        row.signs
+            .iter()
+            .zip(row.descriptors.iter())
+            .filter(|(&s, _)| s != PartedHash::INVALID_SIGN)
+            .map(|(_, &desc)| desc)
+            .collect()
+    }
+
+    pub fn verify(&self, hasher: KeyHasher, report: &mut VerifyReport) -> Result<()> {
+        let file_len = self.file.metadata()?.len();
+
+        for r in 0..ROWS {
+            let row = self.header_row(r);
+
+            for (i, &sign) in row.signs.iter().enumerate() {
+                if sign == PartedHash::INVALID_SIGN {
+                    continue;
+                }
+
+                let desc = row.descriptors[i];
+                let end = desc.offset as u64 + desc.len();
+                report.checked += 1;
+
+                if (desc.offset as u64) < self.data_start() || end > file_len {
+                    report.out_of_bounds += 1;
+                    continue;
+                }
+
+                let (k, _) = self.read(desc)?;
+                let ph = hasher.hash(&k);
+
+                if ph.sign() != sign {
+                    report.sign_mismatch += 1;
+                }
+                if ph.row() != r {
+                    report.row_mismatch += 1;
+                }
+                if !(self.start..self.end).contains(&ph.shard()) {
+                    report.shard_mismatch += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ShardFile {
+    fn drop(&mut self) {
+        // best effort, a stale checksum is reported on the next open
+        let _ = self.sync();
+    }
+}
+
+/// Memory mapped by a [`Store`], see [`Store::memory_footprint`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Bytes of all shard headers, mmapped or read into memory
+    pub mmap_bytes: u64,
+    pub num_shards: usize,
+    /// Size of a single mmapped [`ShardHeader`]
+    pub header_bytes_per_shard: u64,
+}
+
+/// Occupancy of a shard's header, see [`Store::stats`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardStats {
+    pub range: (u32, u32),
+    pub live_slots: usize,
+    pub dead_bytes: u64,
+    /// Longest run of consecutive occupied slots in a row, averaged over the
+    /// rows. A lookup compares signs across the occupied slots of its row, so
+    /// long runs mean slow lookups and a shard due for a split.
+    pub avg_occupied_run: f64,
+    /// Longest run of consecutive occupied slots in any row
+    pub max_run: usize,
+}
+
+/// Tally of the problems found by [`Store::verify`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// No. of live slots inspected
+    pub checked: usize,
+    /// Descriptor points outside the shard's data region
+    pub out_of_bounds: usize,
+    /// Stored key doesn't hash to the slot's sign
+    pub sign_mismatch: usize,
+    /// Stored key hashes to a different row
+    pub row_mismatch: usize,
+    /// Stored key belongs to a different shard's range
+    pub shard_mismatch: usize,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.out_of_bounds == 0
+            && self.sign_mismatch == 0
+            && self.row_mismatch == 0
+            && self.shard_mismatch == 0
+    }
+}
+
+/// Placement of an entry written by [`Store::set_observed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetOutcome {
+    /// `[start, end)` of the shard that absorbed the write
+    pub shard_range: (u32, u32),
+    pub row: usize,
+    pub slot: usize,
+    /// The target row was full, and its shard had to be split first
+    pub split_occurred: bool,
+    /// Value replaced by this write
+    pub previous: Option<Buf>,
+}
+
+/// Log of the operations applied since the last [`Store::sync`], each made
+/// durable before the header is touched so [`Store::recover`] can redo it.
+///
+/// Records are `op: u8, klen: u16, vlen: u16, key, val`, little endian.
+struct Wal {
+    file: File,
+}
+
+impl Wal {
+    const SET: u8 = 0;
+    const REMOVE: u8 = 1;
+    const RECORD_HEADER: usize = 5;
+    const FILENAME: &str = "wal";
+
+    fn open(dirpath: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dirpath.join(Self::FILENAME))?;
+
+        Ok(Self { file })
+    }
+
+    /// Whether the log in `dirpath` holds records not checkpointed yet
+    fn pending(dirpath: &Path) -> Result<bool> {
+        match std::fs::metadata(dirpath.join(Self::FILENAME)) {
+            Ok(meta) => Ok(meta.len() > 0),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append(&self, op: u8, key: &[u8], val: &[u8]) -> Result<()> {
+        let mut record = Vec::with_capacity(Self::RECORD_HEADER + key.len() + val.len());
+        record.push(op);
+        record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        record.extend_from_slice(&(val.len() as u16).to_le_bytes());
+        record.extend_from_slice(key);
+        record.extend_from_slice(val);
+
+        (&self.file).write_all(&record)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    /// All complete records, a torn record at the tail is skipped
+    fn records(&self) -> Result<Vec<(u8, Buf, Buf)>> {
+        let mut buf = vec![0; self.file.metadata()?.len() as usize];
+        self.file.read_exact_at(&mut buf, 0)?;
+
+        let mut records = Vec::new();
+        let mut rest = &buf[..];
+
+        while let Some((header, body)) = rest.split_first_chunk::<{ Self::RECORD_HEADER }>() {
+            let klen = u16::from_le_bytes([header[1], header[2]]) as usize;
+            let vlen = u16::from_le_bytes([header[3], header[4]]) as usize;
+
+            if body.len() < klen + vlen {
+                break;
+            }
+
+            let (key, body) = body.split_at(klen);
+            let (val, body) = body.split_at(vlen);
+            records.push((header[0], key.to_vec(), val.to_vec()));
+            rest = body;
+        }
+
+        Ok(records)
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+/// Options for opening a [`Store`], [`Store::open`] uses the defaults
+#[derive(Debug, Clone)]
+pub struct StoreBuilder {
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    wal: bool,
+    key_normalizer: Option<KeyNormalizer>,
+    tombstones: bool,
+    hasher: KeyHasher,
+}
+
+impl Default for StoreBuilder {
+    fn default() -> Self {
+        Self {
+            truncate: false,
+            create: true,
+            create_new: false,
+            wal: false,
+            key_normalizer: None,
+            tombstones: false,
+            hasher: KeyHasher::SipHash,
+        }
+    }
+}
+
+impl StoreBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Discard the shards (and write-ahead log) already in the directory
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Start an empty store when the directory holds no shards, instead of
+    /// failing with [`Error::NotFound`]
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    /// Fail with [`Error::AlreadyExists`] when the directory holds shards
+    pub fn create_new(mut self, create_new: bool) -> Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Log every `set`/`remove` to a write-ahead log first, see
+    /// [`Store::open_with_wal`]
+    pub fn wal(mut self, wal: bool) -> Self {
+        self.wal = wal;
+        self
+    }
+
+    /// Pass every key through `f` before hashing and storing it, see
+    /// [`Store::open_with_key_normalizer`]
+    pub fn key_normalizer(mut self, f: KeyNormalizer) -> Self {
+        self.key_normalizer = Some(f);
+        self
+    }
+
+    /// Have [`Store::remove`] leave a tombstone rather than free the slot,
+    /// so [`Store::lookup`] tells removed keys from ones never set, e.g. to
+    /// replicate removals. Tombstones take up their slot until the shard is
+    /// compacted.
+    pub fn tombstones(mut self, tombstones: bool) -> Self {
+        self.tombstones = tombstones;
+        self
+    }
+
+    /// Route keys by `hasher` rather than SipHash. It's recorded in the
+    /// store's [`Manifest`], and opening the store with another one fails
+    /// with [`Error::IncompatibleFormat`].
+    pub fn hasher(mut self, hasher: KeyHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    fn file_options(&self) -> OpenOptions {
+        let mut opts = OpenOptions::new();
+        opts.read(true).write(true);
+        opts
+    }
+
+    /// Open the store in `dir`, picking up the shards already there
+    pub fn open(&self, dir: impl AsRef<Path>) -> Result<Store> {
+        let dirpath = dir.as_ref().to_path_buf();
+        let opts = self.file_options();
+
+        if self.create {
+            std::fs::create_dir_all(&dirpath)?;
+        } else if !dirpath.is_dir() {
+            return Err(Error::NotFound { dir: dirpath });
+        }
+
+        let expected = Manifest {
+            hasher: self.hasher,
+            ..Manifest::CURRENT
+        };
+        let manifest = Manifest::read(&dirpath)?;
+        if let Some(found) = manifest {
+            if found != expected && !self.truncate {
+                return Err(Error::IncompatibleFormat { found, expected });
+            }
+        }
+
+        let mut shards = Vec::new();
+        // headers changed since the last checkpoint are checked once the log
+        // is replayed over them
+        let verify = !(self.wal && Wal::pending(&dirpath)?);
+
+        for entry in std::fs::read_dir(&dirpath)? {
+            let name = entry?.file_name();
+            let Some(name_str) = name.to_str() else {
+                continue;
+            };
+
+            // left behind by a shard or manifest write that crashed before its
+            // rename. Other `.tmp` files aren't ours and are left alone
+            if let Some(stem) = name_str.strip_suffix(ShardFile::TMP_SUFFIX) {
+                if stem == Manifest::FILENAME
+                    || matches!(ShardFile::parse_name(&dirpath, stem), Ok(Some(_)))
+                {
+                    std::fs::remove_file(dirpath.join(&name))?;
+                }
+                continue;
+            }
+
+            if let Some((start, end)) = ShardFile::parse_name(&dirpath, name_str)? {
+                if self.truncate {
+                    std::fs::remove_file(dirpath.join(&name))?;
+                } else {
+                    shards.push(ShardFile::open_checked(
+                        &opts, &dirpath, start, end, verify,
+                    )?);
+                }
+            }
+        }
+
+        // a store predating manifests routes by SipHash
+        if manifest.is_none() && !shards.is_empty() && expected != Manifest::CURRENT {
+            return Err(Error::IncompatibleFormat {
+                found: Manifest::CURRENT,
+                expected,
+            });
+        }
+
+        if self.create_new && !shards.is_empty() {
+            return Err(Error::AlreadyExists { dir: dirpath });
+        }
+
+        if shards.is_empty() {
+            if !self.create {
+                return Err(Error::NotFound { dir: dirpath });
+            }
+
+            shards.push(ShardFile::create(&opts, &dirpath, 0, Store::MAX_SHARD)?);
+        }
+        shards.sort_by_key(|x| (x.start, std::cmp::Reverse(x.end)));
+
+        // a split or merge that crashed before removing its source files leaves
+        // shards nested in a wider one. The wider one is only ever renamed into
+        // place complete, and written to once the narrower ones are gone, so
+        // it's kept
+        let found = shards.len();
+        let mut tiling: Vec<ShardFile> = Vec::with_capacity(found);
+        for shard in shards {
+            match tiling.last() {
+                Some(wider) if wider.start <= shard.start && shard.end <= wider.end => {
+                    let (start, end) = (shard.start, shard.end);
+                    drop(shard);
+                    std::fs::remove_file(dirpath.join(format!("{start}-{end}")))?;
+                }
+                _ => tiling.push(shard),
+            }
+        }
+        if tiling.len() != found {
+            sync_dir(&dirpath)?;
+        }
+        let shards = tiling;
+
+        let mut covered = 0;
+        for shard in shards.iter() {
+            if shard.start != covered {
+                return Err(Error::MisalignedShards {
+                    dir: dirpath,
+                    at: covered.min(shard.start),
+                });
+            }
+            covered = shard.end;
+        }
+        if covered != Store::MAX_SHARD {
+            return Err(Error::MisalignedShards {
+                dir: dirpath,
+                at: covered,
+            });
+        }
+
+        if manifest != Some(expected) {
+            expected.write(&dirpath)?;
+        }
+
+        let mut store = Store {
+            dirpath,
+            opts,
+            shards,
+            wal: None,
+            observer: None,
+            key_normalizer: self.key_normalizer,
+            tombstones: self.tombstones,
+            hasher: self.hasher,
+            auto_compact: f64::INFINITY,
+            sync_policy: SyncPolicy::Never,
+            unsynced: 0,
+            last_sync: Instant::now(),
+        };
+
+        if self.wal {
+            let wal = Wal::open(&store.dirpath)?;
+            if self.truncate {
+                wal.clear()?;
+            }
+
+            store.wal = Some(wal);
+            store.recover()?;
+        }
+
+        Ok(store)
+    }
+}
+
+/// Callbacks fired after each successful store operation, for metrics
+/// without depending on a metrics library. Every callback defaults to a no-op.
+pub trait Observer {
+    fn on_get(&self, _elapsed: Duration, _key_len: usize, _found: bool) {}
+    fn on_set(&self, _elapsed: Duration, _key_len: usize, _val_len: usize) {}
+    fn on_remove(&self, _elapsed: Duration, _key_len: usize, _found: bool) {}
+    /// `shard` is the range before the split
+    fn on_split(&self, _elapsed: Duration, _shard: (u32, u32)) {}
+    /// `shard` is the range after the merge
+    fn on_merge(&self, _elapsed: Duration, _shard: (u32, u32)) {}
+    fn on_compact(&self, _elapsed: Duration, _shard: (u32, u32)) {}
+}
+
+/// When the store [`sync`](Store::sync)s on its own, after a `set` or
+/// `remove`, see [`Store::set_sync_policy`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Only on an explicit [`sync`](Store::sync) and when dropped
+    #[default]
+    Never,
+    /// Once every `n` writes
+    EveryN(u64),
+    /// On the first write once the duration passed since the last sync
+    Interval(Duration),
+    /// After every write, the most durable and slowest
+    EveryWrite,
+}
+
+/// Outcome of [`Store::lookup`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetResult {
+    Found(Buf),
+    /// Removed, with the tombstone not yet compacted away
+    Deleted,
+    /// Never set, or removed without a tombstone
+    Missing,
+}
+
+thread_local! {
+    /// Key and value buffers of [`Store::get_with`]
+    static GET_BUFS: Cell<(Buf, Buf)> = const { Cell::new((Vec::new(), Vec::new())) };
+}
+
+pub struct Store {
+    dirpath: PathBuf,
+    opts: OpenOptions,
+    shards: Vec<ShardFile>,
+    wal: Option<Wal>,
+    observer: Option<Box<dyn Observer>>,
+    key_normalizer: Option<KeyNormalizer>,
+    tombstones: bool,
+    hasher: KeyHasher,
+    auto_compact: f64,
+    sync_policy: SyncPolicy,
+    /// Writes since the last sync
+    unsynced: u64,
+    last_sync: Instant,
+}
+
+impl Store {
+    const MAX_SHARD: u32 = u16::MAX as u32 + 1;
+
+    /// Open the store in `dir`, picking up the shards already there or
+    /// creating a single empty one
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        StoreBuilder::default().open(dir)
+    }
+
+    /// Start an empty store in `dir`, failing with [`Error::AlreadyExists`]
+    /// when there is one already
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+        StoreBuilder::default().create_new(true).open(dir)
+    }
+
+    /// Open the store in `dir`, failing with [`Error::NotFound`] when there is
+    /// none
+    pub fn open_existing(dir: impl AsRef<Path>) -> Result<Self> {
+        StoreBuilder::default().create(false).open(dir)
+    }
+
+    /// [`open`](Self::open), logging every `set`/`remove` to a write-ahead log
+    /// in `dir` first. Operations left in the log by a crash are replayed
+    /// through [`recover`](Self::recover) before returning.
+    ///
+    /// [`retain`](Self::retain) and [`for_each_mut`](Self::for_each_mut) aren't
+    /// logged.
+    pub fn open_with_wal(dir: impl AsRef<Path>) -> Result<Self> {
+        StoreBuilder::default().wal(true).open(dir)
+    }
+
+    /// [`open`](Self::open), passing every key given to `get`, `set`,
+    /// `remove` and the like through `f` first, so e.g. lowercasing makes
+    /// the store case-insensitive.
+    ///
+    /// Only the normalized key is stored: `iter` and `keys` return it, and
+    /// the original form is lost unless kept in the value. `f` must be
+    /// idempotent, as keys replayed from the write-ahead log are normalized
+    /// again, and be the same on every open of the store.
+    pub fn open_with_key_normalizer(dir: impl AsRef<Path>, f: KeyNormalizer) -> Result<Self> {
+        StoreBuilder::default().key_normalizer(f).open(dir)
+    }
+
+    /// `key` in the form it's stored under
+    pub(crate) fn normalize<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        match self.key_normalizer {
+            Some(f) => Cow::Owned(f(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Replay the write-ahead log on top of the shards and checkpoint it,
+    /// returning the no. of replayed operations. A no-op without a log.
+    pub fn recover(&mut self) -> Result<usize> {
+        let Some(wal) = self.wal.take() else {
+            return Ok(0);
+        };
+
+        let replayed = wal.records().and_then(|records| {
+            for (op, key, val) in records.iter() {
+                match *op {
+                    Wal::SET => self.set(key, val).map(drop)?,
+                    Wal::REMOVE => self.remove(key).map(drop)?,
+                    op => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("unknown write-ahead log op {op}"),
+                        )
+                        .into())
+                    }
+                }
+            }
+
+            Ok(records.len())
+        });
+
+        self.wal = Some(wal);
+        let replayed = replayed?;
+        self.sync()?;
+
+        Ok(replayed)
+    }
+
+    /// [`ShardFile::sync`] every shard, then checkpoint the write-ahead log
+    pub fn sync(&self) -> Result<()> {
+        #[cfg(test)]
+        counters::count_sync();
+
+        for shard in self.shards.iter() {
+            shard.sync()?;
+        }
+
+        if let Some(wal) = &self.wal {
+            wal.clear()?;
+        }
+
+        Ok(())
+    }
+
+    fn log(&self, op: u8, key: &[u8], val: &[u8]) -> Result<()> {
+        // an entry that can't be stored mustn't be left in the log to replay
+        Descriptor::check_lens(key, val)?;
+
+        match &self.wal {
+            Some(wal) => wal.append(op, key, val),
+            None => Ok(()),
+        }
+    }
+
+    /// [`open`](Self::open), syncing according to `policy`
+    pub fn with_sync_policy(dir: impl AsRef<Path>, policy: SyncPolicy) -> Result<Self> {
+        let mut store = Self::open(dir)?;
+        store.set_sync_policy(policy);
+
+        Ok(store)
+    }
+
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.sync_policy = policy;
+    }
+
+    /// Count a write and [`sync`](Self::sync) when the policy says so
+    fn sync_if(&mut self) -> Result<()> {
+        self.unsynced += 1;
+
+        let due = match self.sync_policy {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryN(n) => self.unsynced >= n,
+            SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::EveryWrite => true,
+        };
+
+        if due {
+            self.sync()?;
+            self.unsynced = 0;
+            self.last_sync = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    /// [`open`](Self::open), reporting every operation to `observer`
+    pub fn with_observer(dir: impl AsRef<Path>, observer: impl Observer + 'static) -> Result<Self> {
+        let mut store = Self::open(dir)?;
+        store.observer = Some(Box::new(observer));
+
+        Ok(store)
+    }
+
+    /// Start timing an operation, only when there's someone to report to
+    fn started(&self) -> Option<Instant> {
+        self.observer.as_ref().map(|_| Instant::now())
+    }
+
+    fn notify(&self, started: Option<Instant>, f: impl FnOnce(&dyn Observer, Duration)) {
+        if let (Some(observer), Some(started)) = (&self.observer, started) {
+            f(observer.as_ref(), started.elapsed());
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Buf>> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        let ph = self.hasher.hash(key);
+
+        let val = self.shards[self.shard_idx(ph)].get(ph, key)?;
+        self.notify(started, |o, elapsed| {
+            o.on_get(elapsed, key.len(), val.is_some())
+        });
+
+        Ok(val)
+    }
+
+    /// [`get`](Self::get) telling a removed key from a missing one, see
+    /// [`StoreBuilder::tombstones`]
+    pub fn lookup(&self, key: &[u8]) -> Result<GetResult> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        let ph = self.hasher.hash(key);
+
+        let res = self.shards[self.shard_idx(ph)].lookup(ph, key)?;
+        self.notify(started, |o, elapsed| {
+            o.on_get(elapsed, key.len(), matches!(res, GetResult::Found(_)))
+        });
+
+        Ok(res)
+    }
+
+    /// [`get`](Self::get) reading into caller provided buffers, so a tight
+    /// read loop doesn't allocate per lookup. `val_buf` holds the value only
+    /// when `true` is returned.
+    pub fn get_into(&self, key: &[u8], key_buf: &mut Buf, val_buf: &mut Buf) -> Result<bool> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        let ph = self.hasher.hash(key);
+
+        let found = self.shards[self.shard_idx(ph)].get_into(ph, key, key_buf, val_buf)?;
+        self.notify(started, |o, elapsed| o.on_get(elapsed, key.len(), found));
+
+        Ok(found)
+    }
+
+    /// [`get`](Self::get) handing the value to `f` rather than returning it.
+    /// The value is read into buffers kept per thread, so a lookup that only
+    /// parses the value doesn't allocate. A `get_with` nested in `f` reads
+    /// into fresh buffers.
+    pub fn get_with<R>(&self, key: &[u8], f: impl FnOnce(&[u8]) -> R) -> Result<Option<R>> {
+        let (mut key_buf, mut val_buf) = GET_BUFS.take();
+
+        let res = self
+            .get_into(key, &mut key_buf, &mut val_buf)
+            .map(|found| found.then(|| f(&val_buf)));
+        GET_BUFS.set((key_buf, val_buf));
+
+        res
+    }
+
+    /// [`get`](Self::get) every key of `keys`, in their order. Lookups are
+    /// grouped by shard and sorted by [`PartedHash::row`] within it, so each
+    /// header row is touched once, in order.
+    pub fn get_sorted(&self, keys: &[&[u8]]) -> Result<Vec<Option<Buf>>> {
+        let keys: Vec<_> = keys.iter().map(|key| self.normalize(key)).collect();
+        let mut lookups: Vec<(usize, PartedHash, usize)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let ph = self.hasher.hash(key);
+                (self.shard_idx(ph), ph, i)
+            })
+            .collect();
+        lookups.sort_unstable_by_key(|&(idx, ph, i)| (idx, ph.row(), i));
+
+        let mut vals = vec![None; keys.len()];
+
+        for (idx, ph, i) in lookups {
+            vals[i] = self.shards[idx].get(ph, &keys[i])?;
+        }
+
+        Ok(vals)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Result<bool> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        self.log(Wal::REMOVE, key, &[])?;
+        let ph = self.hasher.hash(key);
+
+        let idx = self.shard_idx(ph);
+        let found = match self.tombstones {
+            true => self.shards[idx].tombstone(ph, key)?,
+            false => self.shards[idx].remove(ph, key)?,
+        };
+        self.notify(started, |o, elapsed| o.on_remove(elapsed, key.len(), found));
+        self.compact_if(idx)?;
+        self.sync_if()?;
+
+        Ok(found)
+    }
+
+    /// Exchange the values of `a` and `b`, `false` (and no change) if either
+    /// is missing.
+    ///
+    /// Records embed their key, so the slots can't just trade descriptors:
+    /// both records are rewritten first, and only then are both descriptors
+    /// pointed at them.
+    pub fn swap(&mut self, a: &[u8], b: &[u8]) -> Result<bool> {
+        let (a, b) = (self.normalize(a), self.normalize(b));
+        let (a, b) = (&*a, &*b);
+        let (pa, pb) = (self.hasher.hash(a), self.hasher.hash(b));
+        let (sa, sb) = (self.shard_idx(pa), self.shard_idx(pb));
+
+        let Some((slot_a, val_a)) = self.shards[sa].find_live(pa, a)? else {
+            return Ok(false);
+        };
+        let Some((slot_b, val_b)) = self.shards[sb].find_live(pb, b)? else {
+            return Ok(false);
+        };
+
+        self.log(Wal::SET, a, &val_b)?;
+        self.log(Wal::SET, b, &val_a)?;
+
+        let desc_a = self.shards[sa].write(a, &val_b)?;
+        let desc_b = self.shards[sb].write(b, &val_a)?;
+
+        let (shard_a, shard_b) = (&self.shards[sa], &self.shards[sb]);
+        shard_a.point(
+            &mut shard_a.header_row(pa.row()).descriptors[slot_a],
+            desc_a,
+        );
+        shard_b.point(
+            &mut shard_b.header_row(pb.row()).descriptors[slot_b],
+            desc_b,
+        );
+
+        self.compact_if(sa)?;
+        if sb != sa {
+            self.compact_if(sb)?;
+        }
+        self.sync_if()?;
+
+        Ok(true)
+    }
+
+    /// Index of the shard owning `ph`
+    fn shard_idx(&self, ph: PartedHash) -> usize {
+        self.shards
+            .iter()
+            .position(|shard| ph.shard() < shard.end)
+            .unwrap()
+    }
+
+    pub fn split(&mut self, shard_idx: usize) -> Result<()> {
+        let started = self.started();
+        let shard = &self.shards[shard_idx];
+        let (start, end) = (shard.start, shard.end);
+
+        let (top, bottom) = Self::split_files(&self.opts, &self.dirpath, self.hasher, shard)?;
+
+        self.shards[shard_idx] = top;
+        self.shards.push(bottom);
+        self.shards.sort_by_key(|x| x.end);
+        sync_dir(&self.dirpath)?;
+
+        self.notify(started, |o, elapsed| o.on_split(elapsed, (start, end)));
+
+        Ok(())
+    }
+
+    /// Write both halves of `shard` and remove its file, leaving the caller to
+    /// put the halves in its place
+    fn split_files(
+        opts: &OpenOptions,
+        dirpath: &Path,
+        hasher: KeyHasher,
+        shard: &ShardFile,
+    ) -> Result<(ShardFile, ShardFile)> {
+        let start = shard.start;
+        let end = shard.end;
+        let mid = (start + end) / 2;
+        println!("splitting [{start}, {end}) to [{start}, {mid}) and [{mid}, {end})");
+
+        // the source shard stays in place until both halves are fully written
+        // and renamed into place, so a failure midway leaves the store exactly
+        // as it was, and a crash leaves either the halves as `.tmp` files or a
+        // complete overlap that `open` resolves
+        let halves = Self::split_copy(opts, dirpath, hasher, shard, mid).and_then(|halves| {
+            for (lo, hi) in [(start, mid), (mid, end)] {
+                std::fs::rename(
+                    dirpath.join(ShardFile::tmp_name(lo, hi)),
+                    dirpath.join(format!("{lo}-{hi}")),
+                )?;
+            }
+            Ok(halves)
+        });
+        let (top, bottom) = match halves {
+            Ok(halves) => halves,
+            Err(e) => {
+                Self::discard_split(dirpath, start, mid, end);
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = std::fs::remove_file(dirpath.join(format!("{start}-{end}"))) {
+            drop((top, bottom));
+            Self::discard_split(dirpath, start, mid, end);
+            return Err(e.into());
+        }
+
+        Ok((top, bottom))
+    }
+
+    fn split_copy(
+        opts: &OpenOptions,
+        dirpath: &Path,
+        hasher: KeyHasher,
+        shard: &ShardFile,
+        mid: u32,
+    ) -> Result<(ShardFile, ShardFile)> {
+        let top = ShardFile::create_at(
+            opts,
+            dirpath.join(ShardFile::tmp_name(shard.start, mid)),
+            shard.start,
+            mid,
+        )?;
+        let bottom = ShardFile::create_at(
+            opts,
+            dirpath.join(ShardFile::tmp_name(mid, shard.end)),
+            mid,
+            shard.end,
+        )?;
+
+        for res in shard.iter_with_tombstones() {
+            let ((key, val), tombstone) = res?;
+            let ph = hasher.hash(&key);
+            let half = if ph.shard() < mid { &top } else { &bottom };
+
+            if tombstone {
+                half.insert_tombstone(ph, &key)?;
+            } else {
+                half.set(ph, &key, &val)?;
+            }
+        }
+        top.sync()?;
+        bottom.sync()?;
+
+        Ok((top, bottom))
+    }
+
+    fn discard_split(dirpath: &Path, start: u32, mid: u32, end: u32) {
+        // best effort, the halves are never referenced once the split is abandoned
+        for (lo, hi) in [(start, mid), (mid, end)] {
+            let _ = std::fs::remove_file(dirpath.join(ShardFile::tmp_name(lo, hi)));
+            let _ = std::fs::remove_file(dirpath.join(format!("{lo}-{hi}")));
+        }
+    }
+
+    /// Merge shard `idx` with the next one into a single shard covering both
+    /// ranges, the inverse of [`split`](Self::split).
+    ///
+    /// Fails with `InvalidInput`, leaving both untouched, when the shards
+    /// aren't adjacent or their combined entries don't fit into one shard's
+    /// rows.
+    pub fn merge_shards(&mut self, idx: usize) -> Result<()> {
+        let started = self.started();
+        let (Some(lo), Some(hi)) = (self.shards.get(idx), self.shards.get(idx + 1)) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("no shard to merge shard {idx} with"),
+            )
+            .into());
+        };
+
+        if lo.end != hi.start {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "shards [{}, {}) and [{}, {}) are not adjacent",
+                    lo.start, lo.end, hi.start, hi.end
+                ),
+            )
+            .into());
+        }
+
+        for r in 0..ROWS {
+            let live = |shard: &ShardFile| {
+                shard
+                    .header_row(r)
+                    .signs
+                    .iter()
+                    .filter(|&&s| s != PartedHash::INVALID_SIGN)
+                    .count()
+            };
+
+            if live(lo) + live(hi) > WIDTH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "row {r} of shards [{}, {}) and [{}, {}) doesn't fit one shard",
+                        lo.start, lo.end, hi.start, hi.end
+                    ),
+                )
+                .into());
+            }
+        }
+
+        let (start, mid, end) = (lo.start, lo.end, hi.end);
+
+        // written under a `.tmp` name and renamed once synced, so a crash
+        // leaves either that or a complete overlap that `open` resolves
+        let tmppath = self.dirpath.join(ShardFile::tmp_name(start, end));
+        let merged = self.merge_copy(lo, hi, tmppath.clone()).and_then(|merged| {
+            std::fs::rename(&tmppath, self.dirpath.join(format!("{start}-{end}")))?;
+            Ok(merged)
+        });
+        let merged = match merged {
+            Ok(merged) => merged,
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmppath);
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = std::fs::remove_file(self.dirpath.join(format!("{start}-{mid}"))) {
+            drop(merged);
+            let _ = std::fs::remove_file(self.dirpath.join(format!("{start}-{end}")));
+            return Err(e.into());
+        }
+
+        // past this point the merged shard is the only complete copy of the
+        // lower half, so it's kept even if the upper file can't be removed
+        self.shards.remove(idx + 1);
+        self.shards[idx] = merged;
+
+        std::fs::remove_file(self.dirpath.join(format!("{mid}-{end}")))?;
+        sync_dir(&self.dirpath)?;
+
+        self.notify(started, |o, elapsed| o.on_merge(elapsed, (start, end)));
+
+        Ok(())
+    }
+
+    fn merge_copy(&self, lo: &ShardFile, hi: &ShardFile, tmppath: PathBuf) -> Result<ShardFile> {
+        let merged = ShardFile::create_at(&self.opts, tmppath, lo.start, hi.end)?;
+
+        for res in lo.iter_with_tombstones().chain(hi.iter_with_tombstones()) {
+            let ((key, val), tombstone) = res?;
+            let ph = self.hasher.hash(&key);
+
+            if tombstone {
+                merged.insert_tombstone(ph, &key)?;
+            } else {
+                merged.set(ph, &key, &val)?;
+            }
+        }
+        merged.sync()?;
+
+        Ok(merged)
+    }
+
+    /// Rewrite shard `idx` with only its live records, dropping the dead
+    /// ones left behind by overwrites and removals.
+    ///
+    /// The copy is written next to the shard and renamed over it once
+    /// synced, so a failure midway leaves the shard as it was. See
+    /// [`ConcurrentStore::compact_shard`] to keep serving while it's made.
+    pub fn compact_shard(&mut self, idx: usize) -> Result<()> {
+        let started = self.started();
+        let shard = &self.shards[idx];
+        let (start, end) = (shard.start, shard.end);
+        let tmppath = self.dirpath.join(ShardFile::tmp_name(start, end));
+
+        let compacted = self
+            .compact_copy(shard, tmppath.clone())
+            .and_then(|compacted| {
+                std::fs::rename(&tmppath, self.dirpath.join(format!("{start}-{end}")))?;
+                Ok(compacted)
+            });
+        let compacted = match compacted {
+            Ok(compacted) => compacted,
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmppath);
+                return Err(e);
+            }
+        };
+
+        self.shards[idx] = compacted;
+        sync_dir(&self.dirpath)?;
+        self.notify(started, |o, elapsed| o.on_compact(elapsed, (start, end)));
+
+        Ok(())
+    }
+
+    fn compact_copy(&self, shard: &ShardFile, tmppath: PathBuf) -> Result<ShardFile> {
+        let compacted = ShardFile::create_at(&self.opts, tmppath, shard.start, shard.end)?;
+
+        for r in 0..ROWS {
+            compacted.copy_row(shard, r)?;
+        }
+        compacted.sync()?;
+
+        Ok(compacted)
+    }
+
+    /// [`ShardFile::truncate_to_live`] every shard, a cheap partial
+    /// [`compact_shard`](Self::compact_shard) for when the latest records are
+    /// the dead ones. Returns the no. of bytes reclaimed.
+    pub fn truncate_to_live(&mut self) -> Result<u64> {
+        let mut reclaimed = 0;
+
+        for shard in self.shards.iter() {
+            reclaimed += shard.truncate_to_live()?;
+        }
+
+        Ok(reclaimed)
+    }
+
+    /// Compact a single shard, through [`compact_shard`](Self::compact_shard),
+    /// whenever an operation leaves its
+    /// [`space_amplification`](ShardFile::space_amplification) above
+    /// `threshold`. Disabled by default (`f64::INFINITY`).
+    pub fn set_auto_compact(&mut self, threshold: f64) {
+        self.auto_compact = threshold;
+    }
+
+    fn compact_if(&mut self, idx: usize) -> Result<()> {
+        if self.auto_compact.is_finite()
+            && self.shards[idx].space_amplification()? > self.auto_compact
+        {
+            self.compact_shard(idx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert or overwrite `key`.
+    ///
+    /// Keys are capped at 32767 bytes, the high bit of a descriptor's key
+    /// length being reserved for [`append`](Self::append)'s fragments, and
+    /// values at 65535 bytes. Longer ones fail with
+    /// [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput).
+    pub fn set(&mut self, key: &[u8], val: &[u8]) -> Result<bool> {
+        self.set_observed(key, val)?;
+        Ok(true)
+    }
+
+    /// Append `suffix` to the value of `key`, creating it when missing.
+    ///
+    /// Reads the value and writes it back whole for now. The data region being
+    /// append-only, a later version can write just `suffix` as a fragment
+    /// chained to the previous record, marked by its descriptor's `CHAINED`
+    /// bit.
+    pub fn append(&mut self, key: &[u8], suffix: &[u8]) -> Result<()> {
+        let mut val = self.get(key)?.unwrap_or_default();
+        val.extend_from_slice(suffix);
+
+        self.set(key, &val).map(drop)
+    }
+
+    /// [`set`](Self::set), reporting where the entry was placed
+    pub fn set_observed(&mut self, key: &[u8], val: &[u8]) -> Result<SetOutcome> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        self.log(Wal::SET, key, val)?;
+        let ph = self.hasher.hash(key);
+        let mut split_occurred = false;
+
+        loop {
+            let idx = self.shard_idx(ph);
+            let shard = &self.shards[idx];
+
+            if let Some((slot, previous)) = shard.set_at(ph, key, val)? {
+                let outcome = SetOutcome {
+                    shard_range: (shard.start, shard.end),
+                    row: ph.row(),
+                    slot,
+                    split_occurred,
+                    previous,
+                };
+                self.notify(started, |o, elapsed| {
+                    o.on_set(elapsed, key.len(), val.len())
+                });
+                self.compact_if(idx)?;
+                self.sync_if()?;
+
+                return Ok(outcome);
+            }
+
+            self.split(idx)?;
+            split_occurred = true;
+        }
+    }
+
+    /// Insert a key known to be new, skipping the read `set` does on a sign
+    /// match to detect an overwrite.
+    ///
+    /// Misuse on an existing key leaves duplicate entries behind: `get`
+    /// returns the last one written (last-writer-wins, by slot order within
+    /// the row), `remove` brings back the one before it, and `iter` yields
+    /// all of them.
+    pub fn set_blind(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        let key = self.normalize(key);
+        let key = &*key;
+        let started = self.started();
+        self.log(Wal::SET, key, val)?;
+        let ph = self.hasher.hash(key);
+
+        loop {
+            let idx = self.shard_idx(ph);
+
+            if self.shards[idx].set_blind(ph, key, val)? {
+                self.notify(started, |o, elapsed| {
+                    o.on_set(elapsed, key.len(), val.len())
+                });
+                self.compact_if(idx)?;
+                return self.sync_if();
+            }
+
+            self.split(idx)?;
+        }
+    }
+
+    /// Let `f` rewrite the value of every entry, appending the new value
+    /// wherever `f` returns true. Entries stay in their slots.
+    pub fn for_each_mut<F: FnMut(&[u8], &mut Buf) -> bool>(&mut self, mut f: F) -> Result<()> {
+        for idx in 0..self.shards.len() {
+            self.shards[idx].for_each_mut(&mut f)?;
+            self.compact_if(idx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove every entry for which `f(key, value)` returns false. Only the
+    /// signs are cleared, the data region is left as is
+    pub fn retain<F: FnMut(&[u8], &[u8]) -> bool>(&mut self, mut f: F) -> Result<()> {
+        for idx in 0..self.shards.len() {
+            self.shards[idx].retain(&mut f)?;
+            self.compact_if(idx)?;
+        }
+
+        Ok(())
+    }
+
+    /// No. of entries, counted from the mmapped headers without any file
+    /// reads. An estimate, as duplicates left by [`set_blind`](Self::set_blind)
+    /// are counted once per slot, and so are tombstones.
+    pub fn estimated_key_count(&self) -> usize {
+        self.shards.iter().map(|shard| shard.live_slots()).sum()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Result<KV>> + 'a {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    /// [`iter`](Self::iter), tagging each entry with the range of the shard
+    /// it was read from
+    pub fn iter_with_shard<'a>(&'a self) -> impl Iterator<Item = Result<((u32, u32), KV)>> + 'a {
+        self.shards.iter().flat_map(|shard| {
+            let range = (shard.start, shard.end);
+            shard.iter().map(move |res| res.map(|kv| (range, kv)))
+        })
+    }
+
+    /// Every key, without reading the values
+    pub fn keys<'a>(&'a self) -> impl Iterator<Item = Result<Buf>> + 'a {
+        self.shards.iter().flat_map(|shard| shard.keys())
+    }
+
+    /// Check every live slot of every shard, tallying mismatches instead of
+    /// stopping at the first one
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+
+        for shard in self.shards.iter() {
+            shard.verify(self.hasher, &mut report)?;
+        }
+
+        Ok(report)
+    }
+
+    /// [`ShardStats`] of every shard, in the order of
+    /// [`shard_ranges`](Self::shard_ranges)
+    pub fn stats(&self) -> Result<Vec<ShardStats>> {
+        self.shards.iter().map(|shard| shard.stats()).collect()
+    }
+
+    /// Up to `n` arbitrary entries, reading only the first live slots
+    pub fn sample(&self, n: usize) -> Result<Vec<KV>> {
+        self.iter().take(n).collect()
+    }
+
+    /// `(start, end)` of every shard, sorted and covering `[0, 65536)`
+    pub fn shard_ranges(&self) -> Vec<(u32, u32)> {
+        self.shards
+            .iter()
+            .map(|shard| (shard.start, shard.end))
+            .collect()
+    }
+
+    /// Memory taken by the mmapped shard headers, the data regions are read
+    /// through the page cache
+    pub fn memory_footprint(&self) -> MemoryUsage {
+        MemoryUsage {
+            mmap_bytes: self.shards.iter().map(|s| s.header_buf.len() as u64).sum(),
+            num_shards: self.shards.len(),
+            header_bytes_per_shard: ShardFile::HEADER_SIZE,
+        }
+    }
+
+    /// Whether `key` routes to the shard range `range`, as listed by
+    /// [`shard_ranges`](Self::shard_ranges)
+    pub fn owns(&self, range: (u32, u32), key: &[u8]) -> bool {
+        (range.0..range.1).contains(&self.hasher.hash(key).shard())
+    }
+
+    /// Iterate entries whose [`PartedHash::shard`] falls in `[range.0, range.1)`
+    pub fn iter_shard<'a>(&'a self, range: (u32, u32)) -> impl Iterator<Item = Result<KV>> + 'a {
+        let (start, end) = range;
+
+        self.shards
+            .iter()
+            .filter(move |shard| shard.start < end && start < shard.end)
+            .flat_map(|shard| shard.iter())
+            .filter(move |res| match res {
+                Ok((k, _)) => (start..end).contains(&self.hasher.hash(k).shard()),
+                Err(_) => true,
+            })
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        // the shards sync themselves, but the log is only checkpointed here
+        if self.wal.is_some() {
+            let _ = self.sync();
+        }
+    }
+}
+
+// Under Miri: `MIRIFLAGS=-Zmiri-disable-isolation cargo +nightly miri test`.
+// It stops at the first shard mapped, Miri having no file-backed mmaps, so
+// reads against the header can't be checked for aliasing by it yet.
+#[cfg(test)]
+mod store_tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(i: u32) -> [u8; 4] {
+        i.to_le_bytes()
+    }
+
+    /// Files in the store directory besides the manifest
+    fn shard_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name() != Manifest::FILENAME)
+            .count()
+    }
+
+    #[test]
+    fn test_interleaved_shard_reads_and_writes() {
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::new().file_options();
+        let shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+
+        let mut descs = Vec::new();
+        for i in 0..200u32 {
+            let desc = shard.write(&key(i), &i.to_be_bytes()).unwrap();
+            descs.push(desc);
+
+            assert_eq!(
+                shard.read(desc).unwrap(),
+                (key(i).to_vec(), i.to_be_bytes().to_vec())
+            );
+            let j = i / 2;
+            assert_eq!(shard.read_key(descs[j as usize]).unwrap(), key(j));
+        }
+        drop(shard);
+
+        // appends pick up at the end of the file after a reopen
+        let shard = ShardFile::open(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+        let desc = shard.write(b"last", b"one").unwrap();
+        assert_eq!(
+            shard.read(desc).unwrap(),
+            (b"last".to_vec(), b"one".to_vec())
+        );
+        assert_eq!(shard.read_key(descs[199]).unwrap(), key(199));
+    }
+
+    #[test]
+    fn test_get_with_parses_in_place() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..100u32 {
+            db.set(&key(i), &(i as u64 * 3).to_le_bytes()).unwrap();
+        }
+
+        let parse = |v: &[u8]| u64::from_le_bytes(v.try_into().unwrap());
+        assert_eq!(db.get_with(&key(7), parse).unwrap(), Some(21));
+
+        // later lookups reuse the thread's buffer
+        let (k, v) = GET_BUFS.take();
+        let ptr = v.as_ptr();
+        GET_BUFS.set((k, v));
+
+        for i in 0..100u32 {
+            assert_eq!(db.get_with(&key(i), parse).unwrap(), Some(i as u64 * 3));
+        }
+        assert_eq!(db.get_with(b"missing", parse).unwrap(), None);
+
+        let (_, v) = GET_BUFS.take();
+        assert_eq!(v.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_stats_report_the_longest_occupied_run() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        // 100 keys packed into the front of row 0
+        let dense: Vec<_> = (0..)
+            .map(key)
+            .filter(|k| PartedHash::new(k).row() == 0)
+            .take(100)
+            .collect();
+        for k in dense.iter() {
+            db.set(k, b"v").unwrap();
+        }
+        db.set(b"elsewhere", b"v").unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].range, (0, Store::MAX_SHARD));
+        assert_eq!(stats[0].live_slots, 101);
+        assert_eq!(stats[0].max_run, 100);
+        assert_eq!(stats[0].avg_occupied_run, 101.0 / ROWS as f64);
+
+        db.remove(&dense[40]).unwrap();
+        assert_eq!(db.stats().unwrap()[0].max_run, 59);
+    }
+
+    #[test]
+    fn test_append_raw_and_rebuild_index_give_a_queryable_shard() {
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::new().file_options();
+        let shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+
+        let keys: Vec<_> = (0..2000u32).map(key).collect();
+        let vals: Vec<_> = (0..2000u32).map(|i| i.to_be_bytes()).collect();
+        let records: Vec<(&[u8], &[u8])> = keys
+            .iter()
+            .zip(vals.iter())
+            .map(|(k, v)| (&k[..], &v[..]))
+            .collect();
+
+        let descs = shard.append_raw(&records).unwrap();
+        assert_eq!(descs.len(), records.len());
+        assert_eq!(shard.get(PartedHash::new(&key(7)), &key(7)).unwrap(), None);
+
+        let entries: Vec<_> = records
+            .iter()
+            .zip(descs)
+            .map(|(&(k, _), desc)| (k, desc))
+            .collect();
+        shard.rebuild_index(KeyHasher::SipHash, &entries).unwrap();
+
+        for i in 0..2000u32 {
+            assert_eq!(
+                shard.get(PartedHash::new(&key(i)), &key(i)).unwrap(),
+                Some(i.to_be_bytes().to_vec())
+            );
+        }
+        assert_eq!(shard.dead_bytes().unwrap(), 0);
+
+        let mut report = VerifyReport::default();
+        shard.verify(KeyHasher::SipHash, &mut report).unwrap();
+        assert!(report.is_ok());
+
+        // a later set lands after the raw records
+        shard.set(PartedHash::new(b"new"), b"new", b"v").unwrap();
+        assert_eq!(
+            shard.get(PartedHash::new(b"new"), b"new").unwrap(),
+            Some(b"v".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_unmappable_headers_are_read_and_written_back() {
+        let dir = TempDir::new().unwrap();
+        failpoint::force_file_header(true);
+
+        let mut db = Store::open(dir.path()).unwrap();
+        for i in 0..40_000u32 {
+            db.set(&key(i), &i.to_be_bytes()).unwrap();
+        }
+        db.remove(&key(0)).unwrap();
+        assert!(db.shard_ranges().len() > 1);
+        assert!(db.verify().unwrap().is_ok());
+        drop(db);
+
+        // the headers written back load the same whether read or mapped
+        for forced in [true, false] {
+            failpoint::force_file_header(forced);
+            let db = Store::open_existing(dir.path()).unwrap();
+
+            assert_eq!(db.get(&key(0)).unwrap(), None);
+            for i in 1..40_000u32 {
+                assert_eq!(db.get(&key(i)).unwrap(), Some(i.to_be_bytes().to_vec()));
+            }
+            assert_eq!(db.estimated_key_count(), 39_999);
+        }
+    }
+
+    #[test]
+    fn test_remap_grows_header_keeping_data() {
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::new().file_options();
+        let mut shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+
+        for i in 0..500u32 {
+            shard
+                .set(PartedHash::new(&key(i)), &key(i), &i.to_be_bytes())
+                .unwrap();
+        }
+        shard.remove(PartedHash::new(&key(0)), &key(0)).unwrap();
+        let dead = shard.dead_bytes().unwrap();
+
+        let header_len = ShardFile::HEADER_SIZE + 4096;
+        shard.remap(&opts, dir.path(), header_len).unwrap();
+        assert_eq!(shard.data_start(), header_len);
+        assert_eq!(shard.dead_bytes().unwrap(), dead);
+        assert!(shard.remap(&opts, dir.path(), header_len - 1).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // the grown header is picked up on reopen
+        drop(shard);
+        let shard = ShardFile::open(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+        assert_eq!(shard.data_start(), header_len);
+
+        for i in 1..500u32 {
+            let ph = PartedHash::new(&key(i));
+            assert_eq!(
+                shard.get(ph, &key(i)).unwrap(),
+                Some(i.to_be_bytes().to_vec())
+            );
+        }
+        assert_eq!(shard.get(PartedHash::new(&key(0)), &key(0)).unwrap(), None);
+
+        shard
+            .set(PartedHash::new(b"new"), b"new", b"value")
+            .unwrap();
+        assert_eq!(
+            shard.get(PartedHash::new(b"new"), b"new").unwrap(),
+            Some(b"value".to_vec())
+        );
+
+        let mut report = VerifyReport::default();
+        shard.verify(KeyHasher::SipHash, &mut report).unwrap();
+        assert!(report.is_ok());
+        drop(shard);
+
+        // a header region past the end of the file is an error, not a panic
+        let path = dir.path().join(format!("0-{}", Store::MAX_SHARD));
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(
+            &u32::MAX.to_ne_bytes(),
+            offset_of!(ShardHeader, header_len) as u64,
+        )
+        .unwrap();
+        assert!(matches!(
+            ShardFile::open(&opts, dir.path(), 0, Store::MAX_SHARD),
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn test_failed_split_keeps_source_shard() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), &(i * 2).to_le_bytes()).unwrap();
+        }
+
+        failpoint::fail_after(Some(100));
+        let res = db.split(0);
+        failpoint::fail_after(None);
+
+        assert!(res.is_err());
+        assert_eq!(db.shards.len(), 1);
+
+        let files = shard_files(dir.path());
+        assert_eq!(files, 1, "half-written shards must be cleaned up");
+
+        for i in 0..1000u32 {
+            assert_eq!(
+                db.get(&key(i)).unwrap(),
+                Some((i * 2).to_le_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_routes_keys_to_owning_half() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), &(i * 2).to_le_bytes()).unwrap();
+        }
+
+        db.split(0).unwrap();
+
+        assert_eq!(db.shards.len(), 2);
+        assert_eq!(db.iter().count(), 1000);
+
+        for i in 0..1000u32 {
+            assert_eq!(
+                db.get(&key(i)).unwrap(),
+                Some((i * 2).to_le_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample_returns_at_most_n() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        assert!(db.sample(5).unwrap().is_empty());
+
+        for i in 0..100u32 {
+            db.set(&key(i), &key(i)).unwrap();
+        }
+
+        let sample = db.sample(5).unwrap();
+        assert_eq!(sample.len(), 5);
+
+        for (k, v) in sample {
+            assert_eq!(k, v);
+        }
+
+        assert_eq!(db.sample(1000).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_iter_shard_returns_keys_in_range() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), &key(i)).unwrap();
+        }
+
+        db.split(0).unwrap();
+
+        for range in [(0, 32768), (32768, 65536), (1000, 20000), (40000, 40001)] {
+            let mut got: Vec<Buf> = db.iter_shard(range).map(|r| r.unwrap().0).collect();
+            let mut expected: Vec<Buf> = (0..1000u32)
+                .map(|i| key(i).to_vec())
+                .filter(|k| (range.0..range.1).contains(&PartedHash::new(k).shard()))
+                .collect();
+
+            got.sort();
+            expected.sort();
+
+            assert_eq!(got, expected);
+        }
+    }
+
+    #[test]
+    fn test_verify_reports_corruption() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), &key(i)).unwrap();
+        }
+
+        db.split(0).unwrap();
+
+        let report = db.verify().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.checked, 1000);
+
+        let ph = PartedHash::new(&key(7));
+        let shard = db.shards.iter().find(|s| ph.shard() < s.end).unwrap();
+        let row = shard.header_row(ph.row());
+        let slot = row.signs.iter().position(|&s| s == ph.sign()).unwrap();
+
+        // plant a copy of the entry into the wrong row, and break its sign
+        let other = shard.header_row((ph.row() + 1) % ROWS);
+        let free = other.signs.iter().position(|&s| s == 0).unwrap();
+        other.signs[free] = ph.sign();
+        other.descriptors[free] = row.descriptors[slot];
+        row.signs[slot] ^= 1;
+
+        let report = db.verify().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.checked, 1001);
+        assert_eq!(report.sign_mismatch, 1);
+        assert_eq!(report.row_mismatch, 1);
+        assert_eq!(report.shard_mismatch, 0);
+        assert_eq!(report.out_of_bounds, 0);
+
+        row.descriptors[slot].offset = u32::MAX - 2;
+        assert_eq!(db.verify().unwrap().out_of_bounds, 1);
+    }
+
+    #[test]
+    fn test_set_observed_reports_placement() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        let outcome = db.set_observed(b"hello", b"world").unwrap();
+        let ph = PartedHash::new(b"hello");
+
+        assert_eq!(outcome.shard_range, (0, Store::MAX_SHARD));
+        assert_eq!(outcome.row, ph.row());
+        assert_eq!(
+            db.shards[0].header_row(ph.row()).signs[outcome.slot],
+            ph.sign()
+        );
+        assert!(!outcome.split_occurred);
+        assert_eq!(outcome.previous, None);
+
+        let outcome = db.set_observed(b"hello", b"there").unwrap();
+        assert_eq!(outcome.previous, Some(b"world".to_vec()));
+    }
+
+    #[test]
+    fn test_set_observed_reports_split() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        let mut split_at = None;
+
+        for i in 0..(ROWS * WIDTH) as u32 {
+            let outcome = db.set_observed(&key(i), &key(i)).unwrap();
+
+            if outcome.split_occurred {
+                let (start, end) = outcome.shard_range;
+                assert!(end - start < Store::MAX_SHARD);
+
+                split_at = Some(i);
+                break;
+            }
+        }
+
+        let split_at = split_at.expect("a row must have filled up");
+        assert_eq!(db.shards.len(), 2);
+
+        for i in 0..=split_at {
+            assert_eq!(db.get(&key(i)).unwrap(), Some(key(i).to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_merge_undoes_split() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), &key(i)).unwrap();
+        }
+
+        db.split(0).unwrap();
+        db.split(1).unwrap();
+        assert_eq!(db.shards.len(), 3);
+
+        db.merge_shards(1).unwrap();
+        db.merge_shards(0).unwrap();
+
+        assert_eq!(db.shards.len(), 1);
+        assert_eq!(
+            (db.shards[0].start, db.shards[0].end),
+            (0, Store::MAX_SHARD)
+        );
+        assert_eq!(shard_files(dir.path()), 1);
+        assert!(db.verify().unwrap().is_ok());
+
+        for i in 0..1000u32 {
+            assert_eq!(db.get(&key(i)).unwrap(), Some(key(i).to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_merge_requires_adjacent_shards() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        assert!(db.merge_shards(0).is_err());
+
+        db.split(0).unwrap();
+        assert!(db.merge_shards(1).is_err());
+        db.merge_shards(0).unwrap();
+    }
+
+    #[test]
+    fn test_set_blind_skips_reads() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        let reads = counters::reads();
+
+        for i in 0..10_000u32 {
+            db.set_blind(&key(i), &(i * 2).to_le_bytes()).unwrap();
+        }
+
+        assert_eq!(
+            counters::reads(),
+            reads,
+            "set_blind must not read entries back"
+        );
+
+        db.set(&key(1), b"x").unwrap();
+        assert!(
+            counters::reads() > reads,
+            "set must confirm the existing key"
+        );
+
+        for i in 2..10_000u32 {
+            assert_eq!(
+                db.get(&key(i)).unwrap(),
+                Some((i * 2).to_le_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_blind_on_existing_key_duplicates() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        db.set_blind(b"key", b"first").unwrap();
+        db.set_blind(b"key", b"second").unwrap();
+
+        assert_eq!(db.iter().count(), 2);
+        assert_eq!(db.get(b"key").unwrap(), Some(b"second".to_vec()));
+
+        // the latest write wins even when it lands in a lower, freed slot
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::new().file_options();
+        let mut shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+        let (ph, other) = (
+            PartedHash::from_parts(7, 0, 0),
+            PartedHash::from_parts(9, 0, 0),
+        );
+
+        shard.set_blind(other, b"other", b"v").unwrap();
+        shard.set_blind(ph, b"key", b"first").unwrap();
+        assert!(shard.remove(other, b"other").unwrap());
+        shard.set_blind(ph, b"key", b"second").unwrap();
+        assert_eq!(shard.get(ph, b"key").unwrap(), Some(b"second".to_vec()));
+
+        assert!(shard.remove(ph, b"key").unwrap());
+        assert_eq!(shard.get(ph, b"key").unwrap(), Some(b"first".to_vec()));
+    }
+
+    #[test]
+    fn test_short_shard_file_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("0-65536");
+
+        for len in [0, 1, ShardFile::HEADER_SIZE - 1] {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            file.set_len(len).unwrap();
+
+            let err = ShardFile::map(file, 0, Store::MAX_SHARD).err().unwrap();
+            assert!(matches!(err, Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_header_row_out_of_range_panics() {
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::default().file_options();
+        let shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+
+        shard.header_row(ROWS);
+    }
+
+    #[test]
+    fn test_get_into_reuses_buffers() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(
+                &key(i),
+                &i.to_string().repeat(i as usize % 7 + 1).into_bytes(),
+            )
+            .unwrap();
+        }
+
+        let mut key_buf = Vec::new();
+        let mut val_buf = Vec::new();
+
+        for n in 0..10_000u32 {
+            let i = n % 1100;
+            let found = db.get_into(&key(i), &mut key_buf, &mut val_buf).unwrap();
+
+            if i < 1000 {
+                assert!(found);
+                assert_eq!(key_buf, key(i));
+                assert_eq!(
+                    val_buf,
+                    i.to_string().repeat(i as usize % 7 + 1).into_bytes()
+                );
+            } else {
+                assert!(!found);
+            }
+        }
+    }
+
+    #[test]
+    fn test_retain_removes_rejected_keys() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..500u32 {
+            db.set(i.to_string().as_bytes(), b"v").unwrap();
+        }
+
+        db.retain(|k, _| k.len() > 2).unwrap();
+
+        for i in 0..500u32 {
+            let k = i.to_string();
+            assert_eq!(db.get(k.as_bytes()).unwrap().is_some(), k.len() > 2);
+        }
+        assert_eq!(db.iter().count(), 400);
+    }
+
+    #[test]
+    fn test_reopen_keeps_entries() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..5000u32 {
+            db.set(&key(i), &(i * 3).to_le_bytes()).unwrap();
+        }
+        let shards = db.shards.len();
+        drop(db);
+
+        let db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.shards.len(), shards);
+
+        for i in 0..5000u32 {
+            assert_eq!(
+                db.get(&key(i)).unwrap(),
+                Some((i * 3).to_le_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_detects_corrupt_header() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        db.set(b"key", b"val").unwrap();
+        drop(db);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dir.path().join("0-65536"))
+            .unwrap();
+        let mut sign = [0u8; 1];
+        file.read_exact_at(&mut sign, 0).unwrap();
+        file.write_all_at(&[sign[0] ^ 0xff], 0).unwrap();
+
+        let err = Store::open(dir.path()).err().unwrap();
+        assert!(matches!(
+            err,
+            Error::CorruptHeader {
+                shard: (0, Store::MAX_SHARD)
+            }
+        ));
+    }
+
+    #[test]
+    fn test_wal_recovers_write_lost_in_crash() {
+        let dir = TempDir::new().unwrap();
+        let shard_path = dir.path().join("0-65536");
+
+        let mut db = Store::open_with_wal(dir.path()).unwrap();
+        db.set(b"before", b"crash").unwrap();
+        db.sync().unwrap();
+
+        let synced = std::fs::read(&shard_path).unwrap();
+        db.set(b"lost", b"write").unwrap();
+        db.remove(b"before").unwrap();
+
+        // crash: neither the header update nor the appended data reach disk
+        std::mem::forget(db);
+        std::fs::write(&shard_path, &synced).unwrap();
+
+        let mut db = Store::open_with_wal(dir.path()).unwrap();
+        assert_eq!(db.get(b"lost").unwrap(), Some(b"write".to_vec()));
+        assert_eq!(db.get(b"before").unwrap(), None);
+
+        assert_eq!(db.recover().unwrap(), 0, "the log is checkpointed");
+    }
+
+    #[test]
+    fn test_wal_recovers_after_a_crash_without_sync() {
+        let dir = TempDir::new().unwrap();
+
+        let mut db = Store::open_with_wal(dir.path()).unwrap();
+        db.set(b"synced", b"1").unwrap();
+        db.sync().unwrap();
+
+        for i in 0..100u32 {
+            db.set(&key(i), b"v").unwrap();
+        }
+        db.remove(b"synced").unwrap();
+
+        // crash: the headers changed but their checksums were never refreshed
+        std::mem::forget(db);
+        assert!(matches!(
+            Store::open(dir.path()),
+            Err(Error::CorruptHeader { .. })
+        ));
+
+        let db = Store::open_with_wal(dir.path()).unwrap();
+        assert_eq!(db.get(b"synced").unwrap(), None);
+        for i in 0..100u32 {
+            assert_eq!(db.get(&key(i)).unwrap(), Some(b"v".to_vec()));
+        }
+        drop(db);
+
+        // replaying synced the headers, a plain open checks out again
+        assert!(Store::open(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_estimated_key_count_matches_unique_keys() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.estimated_key_count(), 0);
+
+        for i in 0..40_000u32 {
+            db.set(&key(i), b"v").unwrap();
+        }
+        for i in 0..1000u32 {
+            db.remove(&key(i)).unwrap();
+        }
+
+        let reads = counters::reads();
+        assert_eq!(db.estimated_key_count(), db.iter().count());
+        assert_eq!(db.estimated_key_count(), 39_000);
+        assert_eq!(counters::reads() - reads, 39_000, "only iter reads");
+    }
+
+    #[test]
+    fn test_create_and_open_existing_tell_fresh_from_populated() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing");
+
+        assert!(matches!(
+            Store::open_existing(&missing).err(),
+            Some(Error::NotFound { dir }) if dir == missing
+        ));
+        assert!(matches!(
+            Store::open_existing(dir.path()).err(),
+            Some(Error::NotFound { .. })
+        ));
+
+        let mut db = Store::create(&missing).unwrap();
+        db.set(b"k", b"v").unwrap();
+        drop(db);
+
+        assert!(matches!(
+            Store::create(&missing).err(),
+            Some(Error::AlreadyExists { dir }) if dir == missing
+        ));
+
+        let db = Store::open_existing(&missing).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), Some(b"v".to_vec()));
+        drop(db);
+
+        let db = Store::open(&missing).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), Some(b"v".to_vec()));
+        drop(db);
+
+        let fresh = dir.path().join("fresh");
+        assert!(Store::open(&fresh).unwrap().get(b"k").unwrap().is_none());
+        assert!(Store::open_existing(&fresh).is_ok());
+    }
+
+    #[test]
+    fn test_open_skips_stray_files_and_rejects_bad_shard_names() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        db.set(b"k", b"v").unwrap();
+        drop(db);
+
+        for junk in [
+            "README",
+            ".DS_Store",
+            "0-65536.bak",
+            "notes-2024.txt",
+            "a-b",
+            "-5",
+            "7-",
+        ] {
+            std::fs::write(dir.path().join(junk), b"junk").unwrap();
+        }
+
+        let db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.shard_ranges(), [(0, Store::MAX_SHARD)]);
+        assert_eq!(db.get(b"k").unwrap(), Some(b"v".to_vec()));
+        drop(db);
+
+        for bad in ["5-3", "0-70000", "00-65536", "1-99999999999"] {
+            let path = dir.path().join(bad);
+            std::fs::write(&path, b"junk").unwrap();
+
+            assert!(matches!(
+                Store::open(dir.path()).err(),
+                Some(Error::UnexpectedFile { path: p }) if p == path
+            ));
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        assert!(Store::open(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_open_rejects_shards_not_tiling_the_range() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        db.set(b"k", b"v").unwrap();
+        db.split(0).unwrap();
+        drop(db);
+
+        let mid = Store::MAX_SHARD / 2;
+        let top = dir.path().join(format!("0-{mid}"));
+        let misaligned = |err: Option<Error>| match err {
+            Some(Error::MisalignedShards { at, .. }) => Some(at),
+            _ => None,
+        };
+
+        let overlap = dir.path().join(format!("{}-{}", mid / 2, mid + mid / 2));
+        std::fs::copy(&top, &overlap).unwrap();
+        assert_eq!(misaligned(Store::open(dir.path()).err()), Some(mid / 2));
+        std::fs::remove_file(&overlap).unwrap();
+
+        let bottom = dir.path().join(format!("{mid}-{}", Store::MAX_SHARD));
+        let moved = dir.path().join("moved");
+        for (path, at) in [(&top, 0), (&bottom, mid)] {
+            std::fs::rename(path, &moved).unwrap();
+            assert_eq!(misaligned(Store::open(dir.path()).err()), Some(at));
+            std::fs::rename(&moved, path).unwrap();
+        }
+
+        let db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_open_keeps_the_wider_shard_of_a_crashed_split_or_merge() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        for i in 0..1000u32 {
+            db.set(&i.to_le_bytes(), &i.to_be_bytes()).unwrap();
+        }
+        drop(db);
+
+        let mid = Store::MAX_SHARD / 2;
+        let source = dir.path().join(format!("0-{}", Store::MAX_SHARD));
+        let saved = dir.path().join("saved");
+        let halves = [
+            dir.path().join(format!("0-{mid}")),
+            dir.path().join(format!("{mid}-{}", Store::MAX_SHARD)),
+        ];
+        let check = |db: &Store| {
+            assert_eq!(db.shard_ranges(), [(0, Store::MAX_SHARD)]);
+            for i in 0..1000u32 {
+                assert_eq!(
+                    db.get(&i.to_le_bytes()).unwrap(),
+                    Some(i.to_be_bytes().to_vec())
+                );
+            }
+        };
+
+        // the source shard next to both halves, or only the first one
+        for nhalves in [2, 1] {
+            std::fs::copy(&source, &saved).unwrap();
+            let mut db = Store::open(dir.path()).unwrap();
+            db.split(0).unwrap();
+            drop(db);
+            std::fs::rename(&saved, &source).unwrap();
+            for half in &halves[nhalves..] {
+                std::fs::remove_file(half).unwrap();
+            }
+
+            let db = Store::open(dir.path()).unwrap();
+            check(&db);
+            assert!(halves.iter().all(|half| !half.exists()));
+        }
+
+        // the merged shard next to the shards it was merged from
+        let mut db = Store::open(dir.path()).unwrap();
+        db.split(0).unwrap();
+        drop(db);
+        for half in &halves {
+            std::fs::copy(half, half.with_extension("saved")).unwrap();
+        }
+        let mut db = Store::open(dir.path()).unwrap();
+        db.merge_shards(0).unwrap();
+        drop(db);
+        for half in &halves {
+            std::fs::rename(half.with_extension("saved"), half).unwrap();
+        }
+
+        let db = Store::open(dir.path()).unwrap();
+        check(&db);
+        assert!(halves.iter().all(|half| !half.exists()));
+    }
+
+    #[test]
+    fn test_key_normalizer_makes_keys_case_insensitive() {
+        let dir = TempDir::new().unwrap();
+        let mut db =
+            Store::open_with_key_normalizer(dir.path(), |k| k.to_ascii_lowercase()).unwrap();
+
+        db.set(b"foo", b"1").unwrap();
+        assert_eq!(db.get(b"Foo").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(b"FOO").unwrap(), Some(b"1".to_vec()));
+
+        db.set(b"FOO", b"2").unwrap();
+        db.set(b"Bar", b"3").unwrap();
+        assert_eq!(db.get(b"foo").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(
+            db.get_sorted(&[b"BAR", b"fOo"]).unwrap(),
+            [Some(b"3".to_vec()), Some(b"2".to_vec())]
+        );
+
+        // only the normalized form is stored
+        let mut keys = db.keys().collect::<Result<Vec<_>>>().unwrap();
+        keys.sort();
+        assert_eq!(keys, [b"bar".to_vec(), b"foo".to_vec()]);
+
+        assert!(db.remove(b"BaR").unwrap());
+        assert_eq!(db.get(b"bar").unwrap(), None);
+
+        let db = db.into_concurrent().unwrap();
+        assert_eq!(db.get(b"FoO").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_builder_truncate_and_create() {
+        let dir = TempDir::new().unwrap();
+
+        let err = StoreBuilder::new().create(false).open(dir.path()).err();
+        assert!(matches!(err, Some(Error::NotFound { .. })));
+
+        let mut db = Store::open(dir.path()).unwrap();
+        for i in 0..5000u32 {
+            db.set(&key(i), b"kept").unwrap();
+        }
+        drop(db);
+
+        let db = StoreBuilder::new()
+            .truncate(false)
+            .open(dir.path())
+            .unwrap();
+        assert_eq!(db.get(&key(42)).unwrap(), Some(b"kept".to_vec()));
+        assert_eq!(db.iter().count(), 5000);
+        drop(db);
+
+        let db = StoreBuilder::new().truncate(true).open(dir.path()).unwrap();
+        assert_eq!(db.iter().count(), 0);
+        assert_eq!(shard_files(dir.path()), 1);
+    }
+
+    #[test]
+    fn test_for_each_mut_rewrites_values() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..40_000u32 {
+            db.set(&key(i), &i.to_le_bytes()).unwrap();
+        }
+        assert!(db.shards.len() > 1);
+
+        db.for_each_mut(|k, v| {
+            let n = u32::from_le_bytes(v[..].try_into().unwrap());
+            *v = (n * 2).to_le_bytes().to_vec();
+            k != key(7)
+        })
+        .unwrap();
+
+        assert_eq!(db.get(&key(7)).unwrap(), Some(7u32.to_le_bytes().to_vec()));
+        for i in (0..40_000u32).filter(|&i| i != 7) {
+            assert_eq!(
+                db.get(&key(i)).unwrap(),
+                Some((i * 2).to_le_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_exchanges_values() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..40_000u32 {
+            db.set(&key(i), &i.to_le_bytes()).unwrap();
+        }
+
+        let shard_of = |db: &Store, i: u32| db.shard_idx(PartedHash::new(&key(i)));
+        let other = (1..40_000u32)
+            .find(|&i| shard_of(&db, i) != shard_of(&db, 0))
+            .unwrap();
+        let same = (1..40_000u32)
+            .find(|&i| shard_of(&db, i) == shard_of(&db, 0))
+            .unwrap();
+
+        for j in [other, same] {
+            assert!(db.swap(&key(0), &key(j)).unwrap());
+            assert_eq!(db.get(&key(0)).unwrap(), Some(j.to_le_bytes().to_vec()));
+            assert_eq!(db.get(&key(j)).unwrap(), Some(0u32.to_le_bytes().to_vec()));
+
+            assert!(db.swap(&key(0), &key(j)).unwrap());
+            assert_eq!(db.get(&key(0)).unwrap(), Some(0u32.to_le_bytes().to_vec()));
+            assert_eq!(db.get(&key(j)).unwrap(), Some(j.to_le_bytes().to_vec()));
+        }
+
+        assert!(!db.swap(&key(0), b"missing").unwrap());
+        assert_eq!(db.get(&key(0)).unwrap(), Some(0u32.to_le_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_observer_sees_a_single_split() {
+        use std::{cell::Cell, rc::Rc};
+
+        #[derive(Default)]
+        struct Counts {
+            sets: Cell<usize>,
+            gets: Cell<usize>,
+            splits: Cell<usize>,
+        }
+
+        struct Counting(Rc<Counts>);
+
+        impl Observer for Counting {
+            fn on_get(&self, _: Duration, _: usize, _: bool) {
+                self.0.gets.set(self.0.gets.get() + 1);
+            }
+            fn on_set(&self, _: Duration, _: usize, _: usize) {
+                self.0.sets.set(self.0.sets.get() + 1);
+            }
+            fn on_split(&self, _: Duration, shard: (u32, u32)) {
+                assert_eq!(shard, (0, Store::MAX_SHARD));
+                self.0.splits.set(self.0.splits.get() + 1);
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let counts = Rc::new(Counts::default());
+        let mut db = Store::with_observer(dir.path(), Counting(counts.clone())).unwrap();
+
+        let mut i = 0;
+        while db.shards.len() == 1 {
+            db.set(&key(i), b"v").unwrap();
+            i += 1;
+        }
+        db.get(&key(0)).unwrap();
+
+        assert_eq!(counts.splits.get(), 1);
+        assert_eq!(counts.sets.get(), i as usize);
+        assert_eq!(counts.gets.get(), 1);
+    }
+
+    #[test]
+    fn test_memory_footprint_grows_by_a_header_per_split() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        let before = db.memory_footprint();
+        assert_eq!(before.num_shards, 1);
+        assert_eq!(before.mmap_bytes, ShardFile::HEADER_SIZE);
+
+        db.split(0).unwrap();
+        let after = db.memory_footprint();
+
+        assert_eq!(after.num_shards, 2);
+        assert_eq!(after.mmap_bytes, before.mmap_bytes + ShardFile::HEADER_SIZE);
+        assert_eq!(after.header_bytes_per_shard, ShardFile::HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_shard_ranges_partition_the_key_space() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.shard_ranges(), [(0, Store::MAX_SHARD)]);
+
+        for i in 0..40_000u32 {
+            db.set(&key(i), b"v").unwrap();
+        }
+        db.split(0).unwrap();
+        let last = db.shards.len() - 1;
+        db.split(last).unwrap();
+
+        let ranges = db.shard_ranges();
+        assert!(ranges.len() > 3);
+        assert_eq!(ranges.first().unwrap().0, 0);
+        assert_eq!(ranges.last().unwrap().1, Store::MAX_SHARD);
+        assert!(ranges.windows(2).all(|w| w[0].1 == w[1].0));
+
+        for i in 0..1000u32 {
+            let owners = ranges.iter().filter(|&&r| db.owns(r, &key(i))).count();
+            assert_eq!(owners, 1);
+        }
+    }
+
+    #[test]
+    fn test_sync_policy_flushes_after_writes() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        let syncs = counters::syncs();
+        for i in 0..10 {
+            db.set(&key(i), b"v").unwrap();
+        }
+        db.remove(&key(0)).unwrap();
+        assert_eq!(counters::syncs(), syncs, "the default policy never syncs");
+
+        db.set_sync_policy(SyncPolicy::EveryN(1));
+        for i in 0..10 {
+            let syncs = counters::syncs();
+            db.set(&key(i), b"w").unwrap();
+            assert_eq!(counters::syncs(), syncs + 1);
+        }
+        let syncs = counters::syncs();
+        db.remove(&key(1)).unwrap();
+        assert_eq!(counters::syncs(), syncs + 1);
+
+        db.set_sync_policy(SyncPolicy::EveryN(4));
+        let syncs = counters::syncs();
+        for i in 0..8 {
+            db.set(&key(i), b"x").unwrap();
+        }
+        assert_eq!(counters::syncs(), syncs + 2);
+    }
+
+    #[test]
+    fn test_truncate_to_live_drops_dead_tail() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        let file_len = || {
+            std::fs::metadata(dir.path().join(format!("0-{}", Store::MAX_SHARD)))
+                .unwrap()
+                .len()
+        };
+
+        for i in 0..1000 {
+            db.set(&key(i), &[7; 100]).unwrap();
+        }
+        let full = file_len();
+
+        // nothing dead yet, and a dead record in the middle doesn't help
+        db.remove(&key(10)).unwrap();
+        assert_eq!(db.truncate_to_live().unwrap(), 0);
+
+        for i in 500..1000 {
+            db.remove(&key(i)).unwrap();
+        }
+        assert_eq!(db.truncate_to_live().unwrap(), 500 * 104);
+        assert_eq!(file_len(), full - 500 * 104);
+
+        assert_eq!(db.get(&key(499)).unwrap(), Some(vec![7; 100]));
+        assert_eq!(db.get(&key(500)).unwrap(), None);
+
+        db.set(&key(2000), b"after").unwrap();
+        assert_eq!(db.get(&key(2000)).unwrap(), Some(b"after".to_vec()));
+        assert_eq!(db.get(&key(499)).unwrap(), Some(vec![7; 100]));
+    }
+
+    #[test]
+    fn test_heavy_overwrites_auto_compact_once() {
+        use std::{cell::Cell, rc::Rc};
+
+        struct Compactions(Rc<Cell<usize>>);
+
+        impl Observer for Compactions {
+            fn on_compact(&self, _: Duration, shard: (u32, u32)) {
+                assert_eq!(shard, (0, Store::MAX_SHARD));
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let dir = TempDir::new().unwrap();
+        let compactions = Rc::new(Cell::new(0));
+        let mut db = Store::with_observer(dir.path(), Compactions(compactions.clone())).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), &0u32.to_le_bytes()).unwrap();
+        }
+        db.set_auto_compact(2.0);
+
+        // the first pass of overwrites brings the shard to exactly 2x, the
+        // first write of the second pass tips it over
+        for round in 1..=2u32 {
+            for i in 0..1000u32 {
+                db.set(&key(i), &round.to_le_bytes()).unwrap();
+            }
+        }
+
+        assert_eq!(compactions.get(), 1);
+        assert!(db.shards[0].space_amplification().unwrap() < 2.0);
+
+        for i in 0..1000u32 {
+            assert_eq!(db.get(&key(i)).unwrap(), Some(2u32.to_le_bytes().to_vec()));
+        }
+
+        drop(db);
+        let db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.iter().count(), 1000);
+        assert_eq!(db.shards[0].dead_bytes().unwrap(), 999 * 8);
+    }
+
+    #[test]
+    fn test_get_sorted_preserves_input_order() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..40_000u32 {
+            db.set(&key(i), &(i * 3).to_le_bytes()).unwrap();
+        }
+        assert!(db.shards.len() > 1);
+
+        let keys: Vec<[u8; 4]> = (0..500u32).rev().map(|i| key(i * 97)).collect();
+        let mut queried: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        queried.push(b"missing");
+
+        let vals = db.get_sorted(&queried).unwrap();
+        assert_eq!(vals.len(), queried.len());
+
+        for (k, v) in queried.iter().zip(vals.iter()) {
+            assert_eq!(*v, db.get(k).unwrap());
+        }
+        assert_eq!(vals.last().unwrap(), &None);
+    }
+
+    #[test]
+    fn test_append_accumulates_value() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        let mut expected = Vec::new();
+        for i in 0..100u32 {
+            db.append(b"events", &key(i)).unwrap();
+            expected.extend_from_slice(&key(i));
+        }
+
+        assert_eq!(db.get(b"events").unwrap(), Some(expected));
+        assert_eq!(db.iter().count(), 1);
+
+        db.set(b"full", &vec![0; Descriptor::MAX_VAL_LEN]).unwrap();
+        assert!(db.append(b"full", b"x").is_err());
+        assert_eq!(
+            db.get(b"full").unwrap().unwrap().len(),
+            Descriptor::MAX_VAL_LEN
+        );
+    }
+
+    #[test]
+    fn test_colliding_hashes_probe_within_row() {
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::default().file_options();
+        let mut shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+
+        let ph = PartedHash::from_parts(0xdead_beef, 7, 1234);
+        assert_eq!((ph.sign(), ph.row(), ph.shard()), (0xdead_beef, 7, 1234));
+
+        // every key matches the sign, so each lookup has to read past the
+        // entries before it
+        for i in 0..WIDTH as u32 {
+            assert_eq!(
+                shard.set_at(ph, &key(i), &key(i * 2)).unwrap(),
+                Some((i as usize, None))
+            );
+        }
+        for i in 0..WIDTH as u32 {
+            assert_eq!(shard.get(ph, &key(i)).unwrap(), Some(key(i * 2).to_vec()));
+        }
+        assert_eq!(shard.get(ph, b"missing").unwrap(), None);
+
+        // a full row is what makes `Store::set` split
+        assert!(!shard.set(ph, b"one more", b"v").unwrap());
+
+        let prev = Some(key(20).to_vec());
+        assert_eq!(
+            shard.set_at(ph, &key(10), b"new").unwrap(),
+            Some((10, prev))
+        );
+
+        assert!(shard.remove(ph, &key(5)).unwrap());
+        assert!(!shard.remove(ph, &key(5)).unwrap());
+        assert_eq!(shard.get(ph, &key(10)).unwrap(), Some(b"new".to_vec()));
+
+        assert_eq!(
+            shard.set_at(ph, b"one more", b"v").unwrap(),
+            Some((5, None))
+        );
+        assert!(!shard.set(ph, b"and another", b"v").unwrap());
+        assert_eq!(shard.live_slots(), WIDTH);
+
+        let other_row = PartedHash::from_parts(0xdead_beef, 8, 1234);
+        assert!(shard.set(other_row, b"and another", b"v").unwrap());
+    }
+
+    #[test]
+    fn test_full_row_splits_the_shard() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        // keys hashing to the same row, one more than the row holds
+        let keys: Vec<_> = (0u32..)
+            .map(key)
+            .filter(|k| PartedHash::new(k).row() == 7)
+            .take(WIDTH + 1)
+            .collect();
+
+        for k in keys[..WIDTH].iter() {
+            assert!(!db.set_observed(k, k).unwrap().split_occurred);
+        }
+        assert_eq!(db.shard_ranges(), [(0, Store::MAX_SHARD)]);
+
+        let outcome = db.set_observed(&keys[WIDTH], b"last").unwrap();
+        assert!(outcome.split_occurred);
+        assert_eq!(outcome.row, 7);
+        assert!(db.shard_ranges().len() > 1);
+
+        for k in keys[..WIDTH].iter() {
+            assert_eq!(db.get(k).unwrap(), Some(k.to_vec()));
+        }
+        assert_eq!(db.get(&keys[WIDTH]).unwrap(), Some(b"last".to_vec()));
+        assert_eq!(db.iter().count(), WIDTH + 1);
+        assert!(db.verify().unwrap().is_ok());
+
+        // merged back, the row wouldn't fit again
+        assert_eq!(db.shard_ranges().len(), 2);
+        let err = db.merge_shards(0).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+        assert_eq!(db.shard_ranges().len(), 2);
+    }
+
+    #[test]
+    fn test_directory_is_synced_after_shard_files_change() {
+        let dir = TempDir::new().unwrap();
+
+        let syncs = counters::dir_syncs();
+        let mut db = Store::open(dir.path()).unwrap();
+        assert!(counters::dir_syncs() > syncs, "creating a shard");
+
+        for i in 0..1000u32 {
+            db.set(&key(i), b"v").unwrap();
+        }
+
+        for (what, op) in [
+            ("split", Store::split as fn(&mut Store, usize) -> Result<()>),
+            ("merge", Store::merge_shards),
+            ("compaction", Store::compact_shard),
+        ] {
+            let syncs = counters::dir_syncs();
+            op(&mut db, 0).unwrap();
+            assert!(counters::dir_syncs() > syncs, "{what}");
+        }
+        assert_eq!(db.shards.len(), 1);
+    }
+
+    #[test]
+    fn test_slots_with_matches_a_linear_scan() {
+        let dir = TempDir::new().unwrap();
+        let opts = StoreBuilder::default().file_options();
+        let shard = ShardFile::create(&opts, dir.path(), 0, Store::MAX_SHARD).unwrap();
+        let row = shard.header_row(0);
+
+        for (i, s) in row.signs.iter_mut().enumerate() {
+            *s = [7, 9, u32::MAX][i * 31 % 5 % 3];
+        }
+
+        for sign in [7, 9, u32::MAX, 1, PartedHash::INVALID_SIGN] {
+            let expected: Vec<usize> = (0..WIDTH).filter(|&i| row.signs[i] == sign).collect();
+            assert_eq!(row.slots_with(sign).collect::<Vec<_>>(), expected);
+
+            let mut reversed: Vec<usize> = row.slots_with(sign).rev().collect();
+            reversed.reverse();
+            assert_eq!(reversed, expected);
+        }
+    }
+
+    #[test]
+    fn test_open_removes_leftover_tmp_files() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut db = Store::open(dir.path()).unwrap();
+            db.set(b"key", b"val").unwrap();
+        }
+
+        let tmppath = dir.path().join(ShardFile::tmp_name(0, Store::MAX_SHARD));
+        std::fs::write(&tmppath, b"half written").unwrap();
+        let foreign = dir.path().join("foo.tmp");
+        std::fs::write(&foreign, b"not ours").unwrap();
+
+        let db = Store::open(dir.path()).unwrap();
+        assert!(!tmppath.exists());
+        assert_eq!(std::fs::read(&foreign).unwrap(), b"not ours");
+        assert_eq!(db.shard_ranges(), [(0, Store::MAX_SHARD)]);
+        assert_eq!(db.get(b"key").unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn test_stores_work_with_either_hasher() {
+        assert_ne!(
+            KeyHasher::SipHash.hash(b"key"),
+            KeyHasher::Xxh64.hash(b"key")
+        );
+
+        for hasher in [KeyHasher::SipHash, KeyHasher::Xxh64] {
+            let dir = TempDir::new().unwrap();
+            let builder = StoreBuilder::new().hasher(hasher);
+            let mut db = builder.open(dir.path()).unwrap();
+
+            for i in 0..40_000u32 {
+                db.set(&key(i), &key(i * 3)).unwrap();
+            }
+            assert!(db.shard_ranges().len() > 1, "{hasher:?}");
+            assert!(db.verify().unwrap().is_ok(), "{hasher:?}");
+
+            for (range, (k, _)) in db.iter_with_shard().map(Result::unwrap) {
+                assert!(
+                    (range.0..range.1).contains(&hasher.hash(&k).shard()),
+                    "{hasher:?}"
+                );
+            }
+            drop(db);
+
+            let other = match hasher {
+                KeyHasher::SipHash => KeyHasher::Xxh64,
+                KeyHasher::Xxh64 => KeyHasher::SipHash,
+            };
+            assert!(matches!(
+                StoreBuilder::new().hasher(other).open(dir.path()).err(),
+                Some(Error::IncompatibleFormat { found, expected })
+                    if found.hasher == hasher && expected.hasher == other
+            ));
+
+            let db = builder.open(dir.path()).unwrap();
+            for i in 0..40_000u32 {
+                assert_eq!(
+                    db.get(&key(i)).unwrap(),
+                    Some(key(i * 3).to_vec()),
+                    "{hasher:?}"
+                );
+            }
+            assert_eq!(db.get(b"missing").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_removed_keys_stay_deleted_until_compaction() {
+        let dir = TempDir::new().unwrap();
+        let builder = StoreBuilder::new().tombstones(true);
+        let mut db = builder.open(dir.path()).unwrap();
+
+        for i in 0..1000u32 {
+            db.set(&key(i), b"v").unwrap();
+        }
+        for i in 0..500u32 {
+            assert!(db.remove(&key(i)).unwrap());
+        }
+        assert!(!db.remove(&key(0)).unwrap());
+
+        assert_eq!(db.lookup(&key(0)).unwrap(), GetResult::Deleted);
+        assert_eq!(
+            db.lookup(&key(999)).unwrap(),
+            GetResult::Found(b"v".to_vec())
+        );
+        assert_eq!(db.lookup(&key(1000)).unwrap(), GetResult::Missing);
+        assert_eq!(db.get(&key(0)).unwrap(), None);
+        assert_eq!(db.iter().count(), 500);
+
+        // tombstones leave the whole key length to keys
+        let long = vec![7u8; Descriptor::MAX_KEY_LEN];
+        db.set(&long, b"").unwrap();
+        assert_eq!(db.lookup(&long).unwrap(), GetResult::Found(vec![]));
+        assert!(db.remove(&long).unwrap());
+        assert_eq!(db.lookup(&long).unwrap(), GetResult::Deleted);
+
+        // tombstones are carried over on reopen and through a split
+        drop(db);
+        let mut db = builder.open(dir.path()).unwrap();
+        db.split(0).unwrap();
+        assert_eq!(db.lookup(&key(1)).unwrap(), GetResult::Deleted);
+
+        // setting a deleted key brings it back, with no previous value
+        assert_eq!(db.set_observed(&key(2), b"w").unwrap().previous, None);
+        assert_eq!(db.lookup(&key(2)).unwrap(), GetResult::Found(b"w".to_vec()));
+
+        for idx in 0..db.shard_ranges().len() {
+            db.compact_shard(idx).unwrap();
+        }
+        assert_eq!(db.lookup(&key(1)).unwrap(), GetResult::Missing);
+        assert_eq!(db.lookup(&key(2)).unwrap(), GetResult::Found(b"w".to_vec()));
+        assert_eq!(db.estimated_key_count(), 501);
+    }
+
+    #[test]
+    fn test_iter_with_shard_reports_owning_range() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..40_000u32 {
+            db.set(&key(i), b"v").unwrap();
+        }
+        db.split(0).unwrap();
+
+        let ranges = db.shard_ranges();
+        assert!(ranges.len() > 2);
+
+        let mut seen = 0;
+        for res in db.iter_with_shard() {
+            let (range, (k, _)) = res.unwrap();
+
+            assert!(ranges.contains(&range));
+            assert!((range.0..range.1).contains(&PartedHash::new(&k).shard()));
+            seen += 1;
+        }
+        assert_eq!(seen, 40_000);
     }
 }
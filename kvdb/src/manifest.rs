@@ -0,0 +1,140 @@
+use std::{
+    io::{Error as IoError, ErrorKind},
+    path::Path,
+};
+
+use crate::{sync_dir, Descriptor, KeyHasher, Result, ShardFile, FORMAT_VERSION, ROWS, WIDTH};
+
+/// On-disk layout of a store, recorded in its `MANIFEST` file so an
+/// incompatible store is rejected by [`Store::open`](crate::Store::open) as a
+/// whole, with [`Error::IncompatibleFormat`](crate::Error::IncompatibleFormat)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub width: usize,
+    pub rows: usize,
+    /// [`Descriptor`] bits reserved as flags
+    pub descriptor_flags: u16,
+    /// Written out only when it isn't the default SipHash
+    pub hasher: KeyHasher,
+}
+
+impl Manifest {
+    pub const FILENAME: &str = "MANIFEST";
+
+    /// The layout this build reads and writes
+    pub const CURRENT: Self = Self {
+        format_version: FORMAT_VERSION,
+        width: WIDTH,
+        rows: ROWS,
+        descriptor_flags: Descriptor::CHAINED,
+        hasher: KeyHasher::SipHash,
+    };
+
+    /// The manifest in `dir`, `None` for a store predating manifests
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let text = match std::fs::read_to_string(dir.join(Self::FILENAME)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Self::parse(&text).map(Some).ok_or_else(|| {
+            IoError::new(
+                ErrorKind::InvalidData,
+                format!("malformed manifest in {}", dir.display()),
+            )
+            .into()
+        })
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let (mut format_version, mut width, mut rows, mut descriptor_flags) =
+            (None, None, None, None);
+        let mut hasher = KeyHasher::SipHash;
+
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let (key, val) = line.split_once('=')?;
+
+            match key {
+                "format_version" => format_version = Some(val.parse().ok()?),
+                "width" => width = Some(val.parse().ok()?),
+                "rows" => rows = Some(val.parse().ok()?),
+                "descriptor_flags" => descriptor_flags = Some(val.parse().ok()?),
+                "hasher" => hasher = KeyHasher::from_name(val)?,
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            format_version: format_version?,
+            width: width?,
+            rows: rows?,
+            descriptor_flags: descriptor_flags?,
+            hasher,
+        })
+    }
+
+    /// Replace the manifest in `dir`, through a temp file renamed into place
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let mut text = format!(
+            "format_version={}\nwidth={}\nrows={}\ndescriptor_flags={}\n",
+            self.format_version, self.width, self.rows, self.descriptor_flags
+        );
+        if self.hasher != KeyHasher::SipHash {
+            text += &format!("hasher={}\n", self.hasher.name());
+        }
+
+        let tmp = dir.join(format!("{}{}", Self::FILENAME, ShardFile::TMP_SUFFIX));
+        std::fs::write(&tmp, text)?;
+        std::fs::File::open(&tmp)?.sync_all()?;
+        std::fs::rename(&tmp, dir.join(Self::FILENAME))?;
+
+        sync_dir(dir)
+    }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::*;
+    use crate::{Error, Store};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_open_rejects_a_tampered_manifest() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+        db.set(b"k", b"v").unwrap();
+        drop(db);
+
+        assert_eq!(Manifest::read(dir.path()).unwrap(), Some(Manifest::CURRENT));
+
+        let path = dir.path().join(Manifest::FILENAME);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(
+            &path,
+            text.replace(
+                &format!("format_version={FORMAT_VERSION}"),
+                &format!("format_version={}", FORMAT_VERSION + 1),
+            ),
+        )
+        .unwrap();
+
+        let err = Store::open(dir.path()).err();
+        assert!(matches!(
+            err,
+            Some(Error::IncompatibleFormat { found, expected })
+                if found.format_version == FORMAT_VERSION + 1 && expected == Manifest::CURRENT
+        ));
+
+        std::fs::write(&path, "format_version=one\n").unwrap();
+        let err = Store::open(dir.path()).err();
+        assert!(matches!(err, Some(Error::Io(e)) if e.kind() == ErrorKind::InvalidData));
+
+        // a store predating manifests gets one on open
+        std::fs::remove_file(&path).unwrap();
+        let db = Store::open(dir.path()).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), Some(b"v".to_vec()));
+        assert_eq!(Manifest::read(dir.path()).unwrap(), Some(Manifest::CURRENT));
+    }
+}
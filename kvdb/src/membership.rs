@@ -0,0 +1,101 @@
+use crate::{PartedHash, Result, Store};
+
+/// Bloom filter layout: `[k, bits...]`, `k` being the no. of bits set per key
+const HEADER_SIZE: usize = 1;
+
+/// The `k` bit positions of `key` among `nbits`, by double hashing the two
+/// halves of its [`PartedHash`]
+fn positions(key: &[u8], k: u8, nbits: u64) -> impl Iterator<Item = u64> {
+    let h = PartedHash::new(key).0;
+    let (h1, h2) = (h & 0xffff_ffff, (h >> 32) | 1);
+
+    (0..k as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % nbits)
+}
+
+/// Whether `key` may be in the store `filter` was built from, see
+/// [`Store::build_membership_filter`]. `false` is definite.
+///
+/// The filter holds the stored keys, so on a store with a key normalizer
+/// `key` must be passed normalized, see [`Store::open_with_key_normalizer`].
+pub fn membership_check(filter: &[u8], key: &[u8]) -> bool {
+    let Some((&k, bits)) = filter.split_first() else {
+        return true;
+    };
+
+    if bits.is_empty() {
+        return true;
+    }
+
+    positions(key, k, bits.len() as u64 * 8).all(|pos| bits[pos as usize / 8] & 1 << (pos % 8) != 0)
+}
+
+impl Store {
+    /// A Bloom filter of every key, `bits` (rounded up to a whole byte) wide,
+    /// to be queried with [`membership_check`] away from the store.
+    ///
+    /// Only keys are read. The no. of hashes per key is picked for the key
+    /// count and stored in the filter.
+    pub fn build_membership_filter(&self, bits: usize) -> Result<Vec<u8>> {
+        let keys = self.estimated_key_count().max(1);
+        let nbytes = bits.div_ceil(8).max(1);
+        let nbits = nbytes as u64 * 8;
+
+        // optimal for the false positive rate: bits per key * ln 2
+        let k = (nbits as f64 / keys as f64 * std::f64::consts::LN_2).round() as u8;
+        let k = k.clamp(1, 16);
+
+        let mut filter = vec![0; HEADER_SIZE + nbytes];
+        filter[0] = k;
+
+        for key in self.keys() {
+            for pos in positions(&key?, k, nbits) {
+                filter[HEADER_SIZE + pos as usize / 8] |= 1 << (pos % 8);
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod membership_tests {
+    use super::*;
+    use crate::counters;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stored_keys_pass_and_absent_keys_mostly_fail() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path()).unwrap();
+
+        for i in 0..10_000u32 {
+            db.set(&i.to_le_bytes(), &[0; 100]).unwrap();
+        }
+
+        let reads = counters::reads();
+        let filter = db.build_membership_filter(10_000 * 10).unwrap();
+        assert_eq!(counters::reads() - reads, 10_000);
+
+        for i in 0..10_000u32 {
+            assert!(membership_check(&filter, &i.to_le_bytes()));
+        }
+
+        let false_positives = (10_000..20_000u32)
+            .filter(|i| membership_check(&filter, &i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn test_normalized_keys_pass_on_a_normalizing_store() {
+        let dir = TempDir::new().unwrap();
+        let lowercase = |k: &[u8]| k.to_ascii_lowercase();
+        let mut db = Store::open_with_key_normalizer(dir.path(), lowercase).unwrap();
+
+        db.set(b"Foo", b"1").unwrap();
+        let filter = db.build_membership_filter(1024).unwrap();
+
+        assert!(membership_check(&filter, b"foo"));
+        assert!(membership_check(&filter, &lowercase(b"FOO")));
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    os::unix::fs::FileExt,
+    path::Path,
+};
+
+use memmap::Mmap;
+use siphasher::sip::SipHasher24;
+
+use crate::{
+    Buf, Descriptor, Error, KeyHasher, PartedHash, Result, ShardFile, ShardHeader, Store,
+    FORMAT_VERSION, KV, ROWS,
+};
+
+const MAGIC: &[u8; 8] = b"KVDBPACK";
+
+/// `magic, version: u32, shard count: u32`
+const PREAMBLE_SIZE: usize = 16;
+/// `start: u32, end: u32, offset: u64, len: u64` per shard
+const ENTRY_SIZE: usize = 24;
+/// Regions are padded so their header can be viewed in place
+const REGION_ALIGN: usize = 4096;
+
+/// A shard file copied verbatim into the single file
+struct Region {
+    start: u32,
+    end: u32,
+    offset: usize,
+    len: usize,
+}
+
+/// Read-only store packed into a single file by [`Store::export_single_file`].
+///
+/// The file is a preamble, a table of shard regions, then each shard file
+/// byte for byte, so descriptor offsets stay relative to their region.
+pub struct SingleFileStore {
+    mmap: Mmap,
+    regions: Vec<Region>,
+}
+
+fn invalid(msg: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into()
+}
+
+impl SingleFileStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < PREAMBLE_SIZE || &mmap[..8] != MAGIC {
+            return Err(invalid("not a single-file store".to_string()));
+        }
+
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "single-file store has format version {version}, expected {FORMAT_VERSION}"
+            )));
+        }
+
+        let count = u32::from_le_bytes(mmap[12..16].try_into().unwrap()) as usize;
+        let table = mmap
+            .get(PREAMBLE_SIZE..PREAMBLE_SIZE + count * ENTRY_SIZE)
+            .ok_or_else(|| invalid(format!("truncated table of {count} shards")))?;
+
+        let mut regions = Vec::with_capacity(count);
+
+        for entry in table.chunks_exact(ENTRY_SIZE) {
+            let region = Region {
+                start: u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                end: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+                offset: u64::from_le_bytes(entry[8..16].try_into().unwrap()) as usize,
+                len: u64::from_le_bytes(entry[16..24].try_into().unwrap()) as usize,
+            };
+
+            if !region.offset.is_multiple_of(REGION_ALIGN)
+                || region.len < ShardFile::HEADER_SIZE as usize
+                || region.offset.saturating_add(region.len) > mmap.len()
+            {
+                return Err(invalid(format!(
+                    "shard [{}, {}) has a bad region",
+                    region.start, region.end
+                )));
+            }
+
+            regions.push(region);
+        }
+
+        let store = Self { mmap, regions };
+
+        for region in store.regions.iter() {
+            let rows = &store.mmap[region.offset..][..ShardFile::ROWS_SIZE];
+
+            if store.header(region).checksum != SipHasher24::new().hash(rows) {
+                return Err(Error::CorruptHeader {
+                    shard: (region.start, region.end),
+                });
+            }
+        }
+
+        Ok(store)
+    }
+
+    fn header(&self, region: &Region) -> &ShardHeader {
+        unsafe { &*(self.mmap[region.offset..].as_ptr() as *const ShardHeader) }
+    }
+
+    fn read(&self, region: &Region, desc: Descriptor) -> Result<KV> {
+        let start = desc.offset as usize;
+        let mid = start + desc.klen();
+        let end = mid + desc.vlen as usize;
+
+        if end > region.len {
+            return Err(invalid(format!(
+                "entry at {start} overruns shard [{}, {})",
+                region.start, region.end
+            )));
+        }
+
+        let data = &self.mmap[region.offset..][..region.len];
+        Ok((data[start..mid].to_vec(), data[mid..end].to_vec()))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Buf>> {
+        let ph = PartedHash::new(key);
+        let Some(region) = self.regions.iter().find(|r| ph.shard() < r.end) else {
+            return Ok(None);
+        };
+
+        let row = &self.header(region).rows[ph.row()];
+
+        for (i, &s) in row.signs.iter().enumerate().rev() {
+            if s == ph.sign() {
+                let (k, v) = self.read(region, row.descriptors[i])?;
+
+                if k == key {
+                    return Ok((!row.descriptors[i].is_tombstone()).then_some(v));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<KV>> + '_ {
+        self.regions.iter().flat_map(move |region| {
+            (0..ROWS).flat_map(move |r| {
+                let row = &self.header(region).rows[r];
+
+                row.signs
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, &s)| {
+                        s != PartedHash::INVALID_SIGN && !row.descriptors[*i].is_tombstone()
+                    })
+                    .map(move |(i, _)| self.read(region, row.descriptors[i]))
+            })
+        })
+    }
+
+    pub fn shard_ranges(&self) -> Vec<(u32, u32)> {
+        self.regions.iter().map(|r| (r.start, r.end)).collect()
+    }
+}
+
+impl Store {
+    /// Pack every shard into the single file at `path`, see [`SingleFileStore`].
+    /// The file records neither the hasher nor a key normalizer, so only
+    /// SipHash stores without one can be packed.
+    pub fn export_single_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        if self.hasher != KeyHasher::SipHash {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("can't pack a store hashed by {:?}", self.hasher),
+            )
+            .into());
+        }
+        if self.key_normalizer.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "can't pack a store with a key normalizer",
+            )
+            .into());
+        }
+
+        self.sync()?;
+
+        let table_end = PREAMBLE_SIZE + self.shards.len() * ENTRY_SIZE;
+        let mut offset = table_end.next_multiple_of(REGION_ALIGN);
+        let mut regions = Vec::with_capacity(self.shards.len());
+
+        for shard in self.shards.iter() {
+            let len = shard.file.metadata()?.len() as usize;
+
+            regions.push((shard, offset, len));
+            offset = (offset + len).next_multiple_of(REGION_ALIGN);
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        out.write_all(&(self.shards.len() as u32).to_le_bytes())?;
+
+        for &(shard, offset, len) in regions.iter() {
+            out.write_all(&shard.start.to_le_bytes())?;
+            out.write_all(&shard.end.to_le_bytes())?;
+            out.write_all(&(offset as u64).to_le_bytes())?;
+            out.write_all(&(len as u64).to_le_bytes())?;
+        }
+
+        let mut written = table_end;
+
+        for (shard, offset, len) in regions {
+            out.write_all(&vec![0; offset - written])?;
+
+            let mut data = vec![0; len];
+            shard.file.read_exact_at(&mut data, 0)?;
+            out.write_all(&data)?;
+
+            written = offset + len;
+        }
+
+        out.flush()?;
+
+        Ok(())
+    }
+
+    /// Read-only view of a store packed by
+    /// [`export_single_file`](Self::export_single_file)
+    pub fn open_single_file(path: impl AsRef<Path>) -> Result<SingleFileStore> {
+        SingleFileStore::open(path)
+    }
+}
+
+#[cfg(test)]
+mod single_file_tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_export_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path().join("db")).unwrap();
+
+        for i in 0..40_000u32 {
+            db.set(&i.to_le_bytes(), &(i * 5).to_le_bytes()).unwrap();
+        }
+
+        let packed_path = dir.path().join("packed");
+        db.export_single_file(&packed_path).unwrap();
+
+        let packed = Store::open_single_file(&packed_path).unwrap();
+        assert_eq!(packed.shard_ranges(), db.shard_ranges());
+
+        for i in (0..40_000u32).step_by(7) {
+            assert_eq!(
+                packed.get(&i.to_le_bytes()).unwrap(),
+                Some((i * 5).to_le_bytes().to_vec())
+            );
+        }
+        assert_eq!(packed.get(b"missing").unwrap(), None);
+
+        let mut from_db: Vec<KV> = db.iter().collect::<Result<_>>().unwrap();
+        let mut from_packed: Vec<KV> = packed.iter().collect::<Result<_>>().unwrap();
+        from_db.sort();
+        from_packed.sort();
+        assert_eq!(from_db, from_packed);
+    }
+
+    #[test]
+    fn test_corrupt_single_file_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path().join("db")).unwrap();
+        db.set(b"key", b"val").unwrap();
+
+        let packed_path = dir.path().join("packed");
+        db.export_single_file(&packed_path).unwrap();
+
+        let mut bytes = std::fs::read(&packed_path).unwrap();
+        bytes[REGION_ALIGN] ^= 0xff;
+        std::fs::write(&packed_path, &bytes).unwrap();
+
+        assert!(matches!(
+            Store::open_single_file(&packed_path),
+            Err(Error::CorruptHeader { .. })
+        ));
+    }
+
+    #[test]
+    fn test_store_with_key_normalizer_is_not_packed() {
+        let dir = TempDir::new().unwrap();
+        let mut db =
+            Store::open_with_key_normalizer(dir.path().join("db"), |k| k.to_ascii_lowercase())
+                .unwrap();
+        db.set(b"Key", b"val").unwrap();
+
+        let packed_path = dir.path().join("packed");
+        let err = db.export_single_file(&packed_path).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == std::io::ErrorKind::Unsupported));
+        assert!(!packed_path.exists());
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{Buf, Descriptor, PartedHash, Result, Store, KV, ROWS};
+
+/// Reader of a dump written by [`Store::sorted_dump_to_file`]: every entry as
+/// `klen: u32, vlen: u32` (little endian) followed by the key and value
+pub struct DumpReader {
+    reader: BufReader<File>,
+}
+
+impl DumpReader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+
+    fn next_kv(&mut self) -> Result<Option<KV>> {
+        let mut lens = [0u8; 8];
+
+        match self.reader.read_exact(&mut lens) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let klen = u32::from_le_bytes(lens[..4].try_into().unwrap()) as usize;
+        let vlen = u32::from_le_bytes(lens[4..].try_into().unwrap()) as usize;
+
+        let mut k = vec![0; klen];
+        let mut v = vec![0; vlen];
+        self.reader.read_exact(&mut k)?;
+        self.reader.read_exact(&mut v)?;
+
+        Ok(Some((k, v)))
+    }
+}
+
+impl Iterator for DumpReader {
+    type Item = Result<KV>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_kv().transpose()
+    }
+}
+
+fn write_kv(out: &mut impl Write, k: &[u8], v: &[u8]) -> Result<()> {
+    out.write_all(&(k.len() as u32).to_le_bytes())?;
+    out.write_all(&(v.len() as u32).to_le_bytes())?;
+    out.write_all(k)?;
+    out.write_all(v)?;
+    Ok(())
+}
+
+/// Temp files holding the sorted runs, removed on drop
+struct Runs(Vec<PathBuf>);
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in self.0.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Store {
+    /// [`iter`](Self::iter) in byte-lexicographic order of the keys.
+    ///
+    /// The store has no order on disk, so every key is read and held in
+    /// memory up front, along with a shard index and a [`Descriptor`] per
+    /// entry: about `key len + 24` bytes each. Values are read as the
+    /// iterator advances. For stores whose keys don't fit in memory use
+    /// [`sorted_dump_to_file`](Self::sorted_dump_to_file).
+    pub fn iter_sorted<'a>(&'a self) -> Result<impl Iterator<Item = Result<KV>> + 'a> {
+        let mut entries: Vec<(Buf, usize, Descriptor)> = vec![];
+
+        for (idx, shard) in self.shards.iter().enumerate() {
+            for r in 0..ROWS {
+                let row = shard.header_row(r);
+
+                for (&sign, &desc) in row.signs.iter().zip(row.descriptors.iter()) {
+                    if sign != PartedHash::INVALID_SIGN && !desc.is_tombstone() {
+                        entries.push((shard.read_key(desc)?, idx, desc));
+                    }
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(entries
+            .into_iter()
+            .map(|(_, idx, desc)| self.shards[idx].read(desc)))
+    }
+
+    /// Write every entry to `path` sorted by key, readable back with
+    /// [`DumpReader`]. Returns the no. of entries written.
+    ///
+    /// An external merge sort: entries are buffered up to about `run_bytes`
+    /// of keys and values, sorted and spilled to temp files next to `path`,
+    /// which are then merged. Memory stays around `run_bytes` plus one entry
+    /// per run.
+    pub fn sorted_dump_to_file(&self, path: impl AsRef<Path>, run_bytes: usize) -> Result<u64> {
+        let path = path.as_ref();
+        let mut runs = Runs(vec![]);
+        let mut buf: Vec<KV> = vec![];
+        let mut buf_bytes = 0;
+
+        let spill = |buf: &mut Vec<KV>, runs: &mut Runs| -> Result<()> {
+            buf.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+            let mut run_path = path.as_os_str().to_owned();
+            run_path.push(format!(".run{}", runs.0.len()));
+            let run_path = PathBuf::from(run_path);
+
+            let mut out = BufWriter::new(File::create(&run_path)?);
+            runs.0.push(run_path);
+
+            for (k, v) in buf.drain(..) {
+                write_kv(&mut out, &k, &v)?;
+            }
+            out.flush()?;
+
+            Ok(())
+        };
+
+        for res in self.iter() {
+            let (k, v) = res?;
+            buf_bytes += k.len() + v.len();
+            buf.push((k, v));
+
+            if buf_bytes >= run_bytes {
+                spill(&mut buf, &mut runs)?;
+                buf_bytes = 0;
+            }
+        }
+
+        if !buf.is_empty() {
+            spill(&mut buf, &mut runs)?;
+        }
+
+        let mut readers = runs
+            .0
+            .iter()
+            .map(DumpReader::open)
+            .collect::<Result<Vec<_>>>()?;
+
+        // the smallest head of every run, ties broken by run
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some((k, v)) = reader.next_kv()? {
+                heap.push(Reverse((k, i, v)));
+            }
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut written = 0;
+
+        while let Some(Reverse((k, i, v))) = heap.pop() {
+            write_kv(&mut out, &k, &v)?;
+            written += 1;
+
+            if let Some((k, v)) = readers[i].next_kv()? {
+                heap.push(Reverse((k, i, v)));
+            }
+        }
+
+        out.flush()?;
+        out.get_ref().sync_all()?;
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod sorted_tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_iter_sorted_yields_ascending_keys() {
+        let dir = TempDir::new().unwrap();
+        let mut db = Store::open(dir.path().join("db")).unwrap();
+
+        for i in 0..5000u32 {
+            db.set(&i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+        }
+
+        let kvs = db
+            .iter_sorted()
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let expected: Vec<KV> = (0..5000u32)
+            .map(|i| (i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec()))
+            .collect();
+        assert_eq!(kvs, expected);
+
+        // small runs force a multi-way merge
+        let dump = dir.path().join("dump");
+        assert_eq!(db.sorted_dump_to_file(&dump, 4096).unwrap(), 5000);
+
+        let dumped = DumpReader::open(&dump)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(dumped, expected);
+
+        // only the dump is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{io, marker::PhantomData, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Buf, Result, Store};
+
+/// Serialization format used by [`TypedStore`] for keys and values.
+///
+/// Key encoding must be deterministic, equal keys have to produce the same
+/// bytes or they will land on different slots.
+pub trait Codec {
+    fn encode<T: Serialize + ?Sized>(val: &T) -> Result<Buf>;
+    fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T>;
+}
+
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(val: &T) -> Result<Buf> {
+        bincode::serialize(val).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+
+    fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T> {
+        bincode::deserialize(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+}
+
+/// [`Store`] with structured keys and values, encoded through `C` before
+/// hitting the byte-level API
+pub struct TypedStore<K, V, C = Bincode> {
+    store: Store,
+    _marker: PhantomData<fn(K, V, C)>,
+}
+
+impl<K, V, C> TypedStore<K, V, C>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(Store::open(dir)?))
+    }
+
+    pub fn new(store: Store) -> Self {
+        Self {
+            store,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.store.get(&C::encode(key)?)? {
+            Some(v) => Ok(Some(C::decode(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&mut self, key: &K, val: &V) -> Result<bool> {
+        self.store.set(&C::encode(key)?, &C::encode(val)?)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Result<bool> {
+        self.store.remove(&C::encode(key)?)
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn into_inner(self) -> Store {
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod typed_tests {
+    use super::*;
+    use serde::Deserialize;
+    use tempfile::TempDir;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: u64,
+        name: String,
+    }
+
+    #[test]
+    fn test_store_and_retrieve_struct() {
+        let dir = TempDir::new().unwrap();
+        let mut db: TypedStore<u64, User> = TypedStore::open(dir.path()).unwrap();
+
+        for id in 0..100u64 {
+            let user = User {
+                id,
+                name: format!("user-{id}"),
+            };
+            db.set(&id, &user).unwrap();
+        }
+
+        assert_eq!(
+            db.get(&42).unwrap(),
+            Some(User {
+                id: 42,
+                name: "user-42".into()
+            })
+        );
+        assert_eq!(db.get(&1000).unwrap(), None);
+
+        assert!(db.remove(&42).unwrap());
+        assert_eq!(db.get(&42).unwrap(), None);
+        assert_eq!(db.store().iter().count(), 99);
+    }
+}
@@ -37,7 +37,7 @@ fn half_sizes(max: usize) -> impl Iterator<Item = usize> {
 mod xxhash_32 {
     use super::*;
 
-    const TINY_DATA_SIZE: usize = 2;
+    const TINY_DATA_SIZE: usize = 8;
 
     fn tiny_data(c: &mut Criterion) {
         let (seed, data) = gen_data(TINY_DATA_SIZE);
@@ -49,6 +49,15 @@ mod xxhash_32 {
 
             let id = format!("size-{size:02}");
             g.bench_function(id, |b| b.iter(|| XxHash32::oneshot(seed as u32, data)));
+
+            let id = format!("write/size-{size:02}");
+            g.bench_function(id, |b| {
+                b.iter(|| {
+                    let mut hasher = XxHash32::with_seed(seed as u32);
+                    hasher.write(data);
+                    hasher.finish()
+                })
+            });
         }
 
         g.finish();
@@ -98,4 +107,121 @@ mod xxhash_32 {
     criterion_group!(benches, tiny_data, oneshot, streaming);
 }
 
-criterion_main!(xxhash_32::benches);
+mod hashmap {
+    use super::*;
+    use siphasher::sip::SipHasher;
+    use std::{
+        collections::HashMap,
+        hash::{BuildHasher, BuildHasherDefault, RandomState},
+    };
+
+    const N: usize = 100_000;
+
+    fn bench_state<S: BuildHasher + Clone>(
+        g: &mut BenchmarkGroup<'_, WallTime>,
+        name: &str,
+        state: S,
+        keys: &[u64],
+    ) {
+        g.bench_function(format!("{name}/insert"), |b| {
+            b.iter(|| {
+                let mut map = HashMap::with_capacity_and_hasher(keys.len(), state.clone());
+                for &k in keys {
+                    map.insert(k, k);
+                }
+                map
+            })
+        });
+
+        let mut map = HashMap::with_hasher(state);
+        map.extend(keys.iter().map(|&k| (k, k)));
+        g.bench_function(format!("{name}/lookup"), |b| {
+            b.iter(|| keys.iter().map(|k| map[k]).fold(0, u64::wrapping_add))
+        });
+    }
+
+    fn u64_keys(c: &mut Criterion) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let keys: Vec<u64> = (0..N).map(|_| rng.random()).collect();
+
+        let mut g = c.my_benchmark_group("hashmap", "u64_keys");
+        g.throughput(Throughput::Elements(N as _));
+
+        bench_state(
+            &mut g,
+            "xxhash32",
+            xxhash::xxhash32::State::with_seed(0),
+            &keys,
+        );
+        bench_state(
+            &mut g,
+            "xxhash64",
+            xxhash::xxhash64::State::with_seed(0),
+            &keys,
+        );
+        bench_state(&mut g, "std", RandomState::new(), &keys);
+        bench_state(
+            &mut g,
+            "siphash24",
+            BuildHasherDefault::<SipHasher>::default(),
+            &keys,
+        );
+
+        g.finish();
+    }
+
+    criterion_group!(benches, u64_keys);
+}
+
+/// The hashers kvdb could pick between for its keys, one-shot over the
+/// same keys as kvdb hashes them
+mod siphash {
+    use super::*;
+    use siphasher::sip::SipHasher24;
+    use xxhash::XxHash64;
+
+    const KEYS: usize = 1024;
+
+    fn keys(c: &mut Criterion) {
+        let mut g = c.my_benchmark_group("vs_siphash", "keys");
+
+        for size in [4, 8, 32, 256] {
+            let (seed, data) = gen_data(KEYS * size);
+            let keys = data.chunks_exact(size).collect::<Vec<_>>();
+            g.throughput(Throughput::Bytes(data.len() as _));
+
+            let id = format!("xxhash32/size-{size:03}");
+            g.bench_function(id, |b| {
+                b.iter(|| {
+                    keys.iter()
+                        .map(|k| XxHash32::oneshot(seed as u32, k) as u64)
+                        .fold(0, u64::wrapping_add)
+                })
+            });
+
+            let id = format!("xxhash64/size-{size:03}");
+            g.bench_function(id, |b| {
+                b.iter(|| {
+                    keys.iter()
+                        .map(|k| XxHash64::oneshot(seed, k))
+                        .fold(0, u64::wrapping_add)
+                })
+            });
+
+            let id = format!("siphash24/size-{size:03}");
+            g.bench_function(id, |b| {
+                b.iter(|| {
+                    keys.iter()
+                        .map(|k| SipHasher24::new_with_keys(seed, !seed).hash(k))
+                        .fold(0, u64::wrapping_add)
+                })
+            });
+        }
+
+        g.finish();
+    }
+
+    criterion_group!(benches, keys);
+}
+
+criterion_main!(xxhash_32::benches, hashmap::benches, siphash::benches);
@@ -1,7 +1,30 @@
 #![allow(dead_code)]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
 pub mod xxhash32;
+pub mod xxhash64;
+pub use xxhash32::avalanche32;
+pub use xxhash32::hash_str;
 pub use xxhash32::Hasher as XxHash32;
+#[cfg(feature = "std")]
+pub use xxhash32::RandomState;
+pub use xxhash64::Hasher as XxHash64;
+
+#[cfg(feature = "parallel")]
+pub mod parallel;
+#[cfg(feature = "parallel")]
+pub use parallel::hash_parallel;
+
+/// 64-bit digest of `data` in one go, see [`XxHash64::oneshot`]
+#[must_use]
+pub fn oneshot64(seed: u64, data: &[u8]) -> u64 {
+    XxHash64::oneshot(seed, data)
+}
+
+#[cfg(test)]
+mod statistics;
+#[cfg(test)]
+mod testvectors;
 
 pub(crate) trait IntoU32 {
     fn into_u32(self) -> u32;
@@ -14,6 +37,13 @@ impl IntoU32 for u8 {
     }
 }
 
+impl IntoU32 for u16 {
+    #[inline(always)]
+    fn into_u32(self) -> u32 {
+        self.into()
+    }
+}
+
 impl IntoU32 for usize {
     #[inline(always)]
     fn into_u32(self) -> u32 {
@@ -21,6 +51,12 @@ impl IntoU32 for usize {
     }
 }
 
+/// The low 32 bits of `x`, for the places XXH32 wants a value mod 2^32
+#[inline(always)]
+pub(crate) const fn truncate_u32(x: u64) -> u32 {
+    x as u32
+}
+
 pub(crate) trait IntoU64 {
     fn into_u64(self) -> u64;
 }
@@ -32,9 +68,51 @@ impl IntoU64 for u8 {
     }
 }
 
+impl IntoU64 for u16 {
+    #[inline(always)]
+    fn into_u64(self) -> u64 {
+        self.into()
+    }
+}
+
+impl IntoU64 for u32 {
+    #[inline(always)]
+    fn into_u64(self) -> u64 {
+        self.into()
+    }
+}
+
+impl IntoU64 for u64 {
+    #[inline(always)]
+    fn into_u64(self) -> u64 {
+        self
+    }
+}
+
 impl IntoU64 for usize {
     #[inline(always)]
     fn into_u64(self) -> u64 {
         self as u64
     }
 }
+
+#[cfg(test)]
+mod conversion_tests {
+    use super::*;
+
+    #[test]
+    fn widening_conversions_are_lossless() {
+        assert_eq!(u8::MAX.into_u32(), 0xff);
+        assert_eq!(u16::MAX.into_u32(), 0xffff);
+        assert_eq!(u16::MAX.into_u64(), 0xffff);
+        assert_eq!(u32::MAX.into_u64(), 0xffff_ffff);
+        assert_eq!(u64::MAX.into_u64(), u64::MAX);
+        assert_eq!(usize::MAX.into_u64(), usize::MAX as u64);
+    }
+
+    #[test]
+    fn truncate_u32_keeps_the_low_bits() {
+        assert_eq!(truncate_u32(0x0000_0001_004c_cb00), 0x004c_cb00);
+        assert_eq!(truncate_u32(u32::MAX.into()), u32::MAX);
+    }
+}
@@ -0,0 +1,84 @@
+//! Tree hashing of large buffers over all cores.
+//!
+//! The input is cut into [`LEAF_SIZE`] leaves, each hashed on its own with
+//! [`XxHash32::oneshot`]. The digest is then the [`XxHash32`] (same seed) of,
+//! all little endian:
+//!
+//! ```text
+//! leaf digest (u32) ++ leaf length (u64)   for every leaf, in order
+//! total length (u64)
+//! ```
+//!
+//! Leaves don't depend on how the work is split, so neither does the digest.
+//! It is not the sequential xxhash32 of the input.
+
+use crate::XxHash32;
+use core::hash::Hasher as _;
+use rayon::prelude::*;
+
+pub const LEAF_SIZE: usize = 1024 * 1024;
+
+/// Tree hash `data`, spreading its leaves over at most `num_chunks` tasks
+#[must_use]
+pub fn hash_parallel(seed: u32, data: &[u8], num_chunks: usize) -> u32 {
+    let leaves = data.len().div_ceil(LEAF_SIZE);
+    let per_task = leaves.div_ceil(num_chunks.max(1)).max(1);
+
+    let digests: Vec<(u32, usize)> = data
+        .par_chunks(LEAF_SIZE)
+        .with_min_len(per_task)
+        .map(|leaf| (XxHash32::oneshot(seed, leaf), leaf.len()))
+        .collect();
+
+    let mut hasher = XxHash32::with_seed(seed);
+    for (digest, len) in digests {
+        hasher.write(&digest.to_le_bytes());
+        hasher.write(&(len as u64).to_le_bytes());
+    }
+    hasher.write(&(data.len() as u64).to_le_bytes());
+
+    hasher.finish_32()
+}
+
+#[cfg(test)]
+mod parallel_tests {
+    use super::*;
+
+    fn input(len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn digest_does_not_depend_on_the_split() {
+        let data = input(5 * LEAF_SIZE + 12_345);
+        let expected = hash_parallel(7, &data, 1);
+
+        for num_chunks in [0, 2, 3, 6, 64] {
+            assert_eq!(
+                hash_parallel(7, &data, num_chunks),
+                expected,
+                "{num_chunks} chunks"
+            );
+        }
+
+        let mut flipped = data.clone();
+        flipped[3 * LEAF_SIZE + 1] ^= 1;
+        assert_ne!(hash_parallel(7, &flipped, 4), expected);
+        assert_ne!(hash_parallel(7, &data[..data.len() - 1], 4), expected);
+        assert_ne!(hash_parallel(8, &data, 4), expected);
+    }
+
+    #[test]
+    fn short_inputs_differ_by_length() {
+        let zeros = [0u8; 4];
+        let digests: Vec<u32> = (0..=zeros.len())
+            .map(|len| hash_parallel(0, &zeros[..len], 4))
+            .collect();
+
+        for (i, a) in digests.iter().enumerate() {
+            assert!(!digests[i + 1..].contains(a), "length {i} collides");
+        }
+    }
+}
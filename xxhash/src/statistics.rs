@@ -0,0 +1,68 @@
+//! Distribution checks on top of the fixed reference vectors, which a broken
+//! `round` or finalizer could still happen to pass for their specific inputs.
+
+use crate::{XxHash32, XxHash64};
+
+const INPUTS: u32 = 100_000;
+
+/// Fraction of `INPUTS` sequential integers setting each output bit
+fn bit_frequencies(bits: u32, hash: impl Fn(&[u8]) -> u64) -> Vec<f64> {
+    let mut set = vec![0u32; bits as usize];
+
+    for i in 0..INPUTS {
+        let digest = hash(&i.to_le_bytes());
+
+        for (bit, count) in set.iter_mut().enumerate() {
+            *count += (digest >> bit) as u32 & 1;
+        }
+    }
+
+    set.iter().map(|&n| n as f64 / INPUTS as f64).collect()
+}
+
+/// Mean no. of output bits flipped by flipping a single input bit
+fn mean_avalanche(hash: impl Fn(&[u8]) -> u64) -> f64 {
+    let mut flipped = 0u64;
+    let mut trials = 0u64;
+
+    for i in 0..INPUTS / 10 {
+        let input = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15).to_le_bytes();
+        let digest = hash(&input);
+
+        for bit in 0..64 {
+            let mut flipped_input = input;
+            flipped_input[bit / 8] ^= 1 << (bit % 8);
+
+            flipped += (hash(&flipped_input) ^ digest).count_ones() as u64;
+            trials += 1;
+        }
+    }
+
+    flipped as f64 / trials as f64
+}
+
+fn assert_uniform(bits: u32, hash: impl Fn(&[u8]) -> u64 + Copy) {
+    for (bit, freq) in bit_frequencies(bits, hash).into_iter().enumerate() {
+        assert!(
+            (0.49..0.51).contains(&freq),
+            "bit {bit} set {freq:.4} of the time"
+        );
+    }
+
+    let avalanche = mean_avalanche(hash);
+    let half = bits as f64 / 2.0;
+    assert!(
+        (avalanche - half).abs() < 0.1,
+        "a single bit flip changes {avalanche:.3} output bits, expected ~{half}"
+    );
+}
+
+#[test]
+fn xxhash32_output_is_uniform() {
+    assert_uniform(32, |data| XxHash32::oneshot(0, data).into());
+}
+
+#[test]
+fn xxhash64_output_is_uniform() {
+    assert_uniform(64, |data| XxHash64::oneshot(0, data));
+}
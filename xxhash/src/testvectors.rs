@@ -0,0 +1,189 @@
+//! Reference digests of the canonical C implementation, shared by the 32 and
+//! 64-bit hashers so neither can drift on its own.
+
+use crate::{XxHash32, XxHash64};
+use core::hash::Hasher as _;
+
+/// `(seed, input_len, expected32, expected64)`, see [`input`] for the bytes
+pub(crate) const VECTORS: &[(u32, usize, u32, u64)] = &[
+    (0, 0, 0x02cc_5d05, 0xef46_db37_51d8_e999),
+    (0, 1, 0xe0fe_705f, 0x0a9e_dece_beb0_3ae4),
+    (0, 14, 0x9e5e_7e93, 0x7b06_c531_ea43_e89f),
+    (0, 100, 0x7f89_ba44, 0x6ac1_e580_3216_6597),
+    (0x42c9_1977, 0, 0xd6bf_8459, 0xbf2d_6536_0c1c_7f67),
+    (0x42c9_1977, 100, 0x6d2f_6c17, 0xd4bf_85ce_66f2_dbd1),
+];
+
+/// The input hashed for a vector of the given length
+pub(crate) fn input(len: usize) -> Vec<u8> {
+    match len {
+        1 => vec![42],
+        14 => b"Hello, world!\0".to_vec(),
+        _ => (0..len).map(|i| i as u8).collect(),
+    }
+}
+
+#[test]
+fn xxhash32_matches_vectors() {
+    for &(seed, len, expected, _) in VECTORS {
+        let data = input(len);
+
+        assert_eq!(
+            XxHash32::oneshot(seed, &data),
+            expected,
+            "oneshot, seed {seed:#x}, len {len}"
+        );
+
+        let mut hasher = XxHash32::with_seed(seed);
+        for chunk in data.chunks(3) {
+            hasher.write(chunk);
+        }
+
+        assert_eq!(
+            hasher.finish_32(),
+            expected,
+            "streaming, seed {seed:#x}, len {len}"
+        );
+    }
+}
+
+#[test]
+fn xxhash64_matches_vectors() {
+    for &(seed, len, _, expected) in VECTORS {
+        let data = input(len);
+        let seed = seed as u64;
+
+        assert_eq!(
+            XxHash64::oneshot(seed, &data),
+            expected,
+            "oneshot, seed {seed:#x}, len {len}"
+        );
+
+        let mut hasher = XxHash64::with_seed(seed);
+        for chunk in data.chunks(3) {
+            hasher.write(chunk);
+        }
+
+        assert_eq!(
+            hasher.finish(),
+            expected,
+            "streaming, seed {seed:#x}, len {len}"
+        );
+    }
+}
+
+/// Tests of the streaming API run against both hashers. Expanded in each
+/// module's tests, where its private `Accumulator`, `Lanes` and
+/// `BYTES_IN_LANE` are in scope: `$expected` is the [`VECTORS`] field with
+/// the module's digests, `$digest` its full width digest method.
+macro_rules! hasher_tests {
+    ($digest:ident, $expected:tt) => {
+        #[test]
+        fn short_first_writes_match_reference_vectors() {
+            use crate::testvectors::{input, VECTORS};
+
+            for vector in VECTORS {
+                let (seed, len, expected) = (vector.0.into(), vector.1, vector.$expected);
+                let data = input(len);
+
+                // the first write takes the short path, the second the buffered one
+                for split in 0..BYTES_IN_LANE.min(len + 1) {
+                    let mut hasher = Hasher::with_seed(seed);
+                    hasher.write(&data[..split]);
+                    hasher.write(&data[split..]);
+
+                    assert_eq!(
+                        hasher.$digest(),
+                        expected,
+                        "seed {seed:#x}, split {split}/{len}"
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn extend_matches_write_of_the_collected_bytes() {
+            let bytes: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+
+            let mut written = Hasher::with_seed(9);
+            written.write(&bytes);
+
+            let mut by_byte = Hasher::with_seed(9);
+            by_byte.extend(bytes.iter().copied());
+
+            let mut by_chunk = Hasher::with_seed(9);
+            by_chunk.extend(bytes.chunks(7));
+
+            assert_eq!(by_byte.$digest(), written.$digest());
+            assert_eq!(by_chunk.$digest(), written.$digest());
+        }
+
+        #[cfg(feature = "std")]
+        #[test]
+        fn io_copy_into_the_hasher_matches_oneshot() {
+            let data = crate::testvectors::input(1000);
+
+            let mut hasher = Hasher::with_seed(7);
+            let copied = std::io::copy(&mut std::io::Cursor::new(&data), &mut hasher).unwrap();
+
+            assert_eq!(copied, data.len() as u64);
+            assert_eq!(hasher.$digest(), Hasher::oneshot(7, &data));
+        }
+
+        #[test]
+        fn writes_after_finish_continue_the_stream() {
+            let data = crate::testvectors::input(200);
+
+            for a in [
+                0,
+                1,
+                7,
+                BYTES_IN_LANE - 1,
+                BYTES_IN_LANE,
+                BYTES_IN_LANE + 3,
+                100,
+            ] {
+                for b in [0, 1, 5, BYTES_IN_LANE, 2 * BYTES_IN_LANE + 1] {
+                    let mut hasher = Hasher::with_seed(0x1234);
+                    hasher.write(&data[..a]);
+                    let h1 = hasher.$digest();
+                    hasher.write(&data[a..a + b]);
+                    let h2 = hasher.$digest();
+
+                    assert_eq!(h1, Hasher::oneshot(0x1234, &data[..a]), "{a}+{b}");
+                    assert_eq!(h2, Hasher::oneshot(0x1234, &data[..a + b]), "{a}+{b}");
+                    assert_eq!(hasher.$digest(), h2, "finish is repeatable");
+                }
+            }
+        }
+
+        /// The digest a big-endian host computes: its native loads see each
+        /// lane byte-swapped, which `Accumulator::write_loaded` swaps back
+        #[test]
+        fn big_endian_lane_loads_match_reference_vectors() {
+            use crate::testvectors::{input, VECTORS};
+
+            const LANE: usize = core::mem::size_of::<Lane>();
+
+            for vector in VECTORS {
+                let (seed, len, expected) = (vector.0.into(), vector.1, vector.$expected);
+                let data = input(len);
+                let mut accumulator = Accumulator::new(seed);
+                let mut chunks = data.chunks_exact(BYTES_IN_LANE);
+
+                for chunk in &mut chunks {
+                    let lanes: Lanes = array::from_fn(|i| {
+                        Lane::from_be_bytes(chunk[i * LANE..][..LANE].try_into().unwrap())
+                    });
+                    accumulator.write_loaded(lanes, true);
+                }
+
+                let digest =
+                    Hasher::finish_with(seed, data.len() as u64, &accumulator, chunks.remainder());
+                assert_eq!(digest, expected, "seed {seed:#x}, len {len}");
+            }
+        }
+    };
+}
+
+pub(crate) use hasher_tests;
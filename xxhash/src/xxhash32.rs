@@ -1,7 +1,9 @@
 #![allow(dead_code)]
 
-use crate::{IntoU32, IntoU64};
-use std::hash::BuildHasher;
+use crate::{truncate_u32, IntoU32, IntoU64};
+use core::hash::BuildHasher;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
 
 const PRIME32_1: u32 = 0x9E3779B1;
 const PRIME32_2: u32 = 0x85EBCA77;
@@ -9,18 +11,28 @@ const PRIME32_3: u32 = 0xC2B2AE3D;
 const PRIME32_4: u32 = 0x27D4EB2F;
 const PRIME32_5: u32 = 0x165667B1;
 
+/// Bytes read per fill by [`Hasher::update_reader`]
+#[cfg(feature = "std")]
+const READ_BUF_SIZE: usize = 64 * 1024;
+
 type Lane = u32;
 type Lanes = [Lane; 4];
 type Bytes = [u8; 16];
 
-// compile time assertion to verify alignment
-const _: () = assert!(std::mem::size_of::<u8>() <= std::mem::size_of::<u32>());
+// compile time assertion backing the `Lanes` <-> `Bytes` casts of `BufferedData`
+const _: () = assert!(
+    core::mem::size_of::<Bytes>() == core::mem::size_of::<Lanes>()
+        && core::mem::align_of::<Bytes>() <= core::mem::align_of::<Lanes>()
+);
 
-const BYTES_IN_LANE: usize = std::mem::size_of::<Bytes>();
+const BYTES_IN_LANE: usize = core::mem::size_of::<Bytes>();
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 struct BufferedData(Lanes);
 
+/// The bytes are viewed in place through the lanes: `Bytes` has the same size
+/// as `Lanes` and an alignment of 1, so the cast is valid for any `Lanes`
+/// address. Input slices are only ever copied in, never cast.
 impl BufferedData {
     const fn new() -> Self {
         Self([0; 4])
@@ -35,8 +47,8 @@ impl BufferedData {
     }
 }
 
-impl std::fmt::Debug for BufferedData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Debug for BufferedData {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_list().entries(self.0).finish()
     }
 }
@@ -63,7 +75,7 @@ mod buffer_data_tests {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 struct Buffer {
     offset: usize,
     data: BufferedData,
@@ -134,6 +146,36 @@ impl Buffer {
 mod buffer_tests {
     use super::*;
 
+    /// Meant for `cargo +nightly miri test` as well, every input starts at a
+    /// different offset from a `u32` boundary
+    #[test]
+    fn test_misaligned_inputs_round_trip() {
+        let backing: Lanes = [0x0302_0100, 0x0706_0504, 0x0b0a_0908, 0x0f0e_0d0c];
+        let storage: Vec<u8> = (0..64).collect();
+
+        for misalign in 0..4 {
+            let input = &storage[misalign..misalign + 32];
+
+            for split in 1..BYTES_IN_LANE {
+                let mut buf = Buffer::new();
+                buf.set(&input[..split]);
+                assert_eq!(buf.remaining(), &input[..split]);
+
+                let (lanes, rest) = buf.extend(&input[split..]);
+                let lanes = *lanes.expect("a full buffer is emitted");
+
+                let emitted: Vec<u8> = lanes.iter().flat_map(|l| l.to_ne_bytes()).collect();
+                assert_eq!(emitted, &input[..BYTES_IN_LANE]);
+                assert_eq!(rest, &input[BYTES_IN_LANE..]);
+                assert!(buf.remaining().is_empty());
+            }
+        }
+
+        let mut data = BufferedData(backing);
+        assert_eq!(data.bytes_mut().len(), 16);
+        assert_eq!(data.bytes(), &backing.map(u32::to_ne_bytes).concat()[..]);
+    }
+
     #[test]
     fn test_set_and_remaining() {
         let mut buf = Buffer::new();
@@ -201,7 +243,7 @@ mod buffer_tests {
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 struct Accumulator(Lanes);
 
 impl Accumulator {
@@ -215,15 +257,27 @@ impl Accumulator {
         ])
     }
 
+    /// `lanes` are as loaded from the input in native order, each is read as
+    /// little endian
     #[inline]
     fn write(&mut self, lanes: Lanes) {
+        self.write_loaded(lanes, cfg!(target_endian = "big"));
+    }
+
+    /// [`write`](Self::write) of `lanes` loaded on a host of the given
+    /// endianness, a big-endian one's being byte-swapped back
+    #[inline(always)]
+    fn write_loaded(&mut self, lanes: Lanes, big_endian: bool) {
         let [acc1, acc2, acc3, acc4] = &mut self.0;
-        let [l1, l2, l3, l4] = lanes;
+        let [l1, l2, l3, l4] = match big_endian {
+            true => lanes.map(Lane::swap_bytes),
+            false => lanes,
+        };
 
-        *acc1 = Self::round(*acc1, l1.to_le());
-        *acc2 = Self::round(*acc2, l2.to_le());
-        *acc3 = Self::round(*acc3, l3.to_le());
-        *acc4 = Self::round(*acc4, l4.to_le());
+        *acc1 = Self::round(*acc1, l1);
+        *acc2 = Self::round(*acc2, l2);
+        *acc3 = Self::round(*acc3, l3);
+        *acc4 = Self::round(*acc4, l4);
     }
 
     #[inline]
@@ -259,8 +313,8 @@ impl Accumulator {
     }
 }
 
-impl std::fmt::Debug for Accumulator {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Debug for Accumulator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let [acc1, acc2, acc3, acc4] = self.0;
 
         f.debug_struct("Accumulator")
@@ -300,6 +354,7 @@ mod accumulator_tests {
     }
 
     #[test]
+    #[allow(clippy::absurd_extreme_comparisons)]
     fn test_write_and_finish() {
         let mut acc = Accumulator::new(0);
         acc.write([1, 2, 3, 4]);
@@ -337,7 +392,7 @@ mod accumulator_tests {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Hasher {
     seed: u32,
     length: u64,
@@ -375,6 +430,18 @@ impl Hasher {
         }
     }
 
+    /// Construct the hasher from a 64-bit seed.
+    ///
+    /// The seed is multiplied by the 64-bit golden ratio, then its halves are
+    /// xor-ed into the 32-bit seed, so salts differing only in their high or
+    /// low half still map to different seeds.
+    #[must_use]
+    pub const fn with_seed64(seed: u64) -> Self {
+        let mixed = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+
+        Self::with_seed((mixed ^ (mixed >> 32)) as u32)
+    }
+
     /// The seed used to create this hasher
     pub const fn seed(&self) -> u32 {
         self.seed
@@ -389,10 +456,14 @@ impl Hasher {
     ///
     /// For the full 64-bit count use [`total_len`](Self::total_len)
     pub const fn total_len_32(&self) -> u32 {
-        self.length as u32
+        truncate_u32(self.length)
     }
 
     /// Returns the hash value for the input data so far.
+    ///
+    /// A snapshot, not a terminator: the hasher is left as is, so more data
+    /// can be written and the next digest covers everything written since
+    /// [`with_seed`](Self::with_seed).
     #[must_use]
     #[inline]
     pub fn finish_32(&self) -> u32 {
@@ -404,6 +475,54 @@ impl Hasher {
         )
     }
 
+    /// Feed `data` and hand the hasher back, for fluent hashing of several
+    /// fields: `Hasher::with_seed(0).chain(b"a").chain(b"b").digest()`
+    #[must_use]
+    #[inline]
+    pub fn chain(mut self, data: &[u8]) -> Self {
+        core::hash::Hasher::write(&mut self, data);
+        self
+    }
+
+    /// Same as [`finish_32`](Self::finish_32), reads better at the end of a
+    /// [`chain`](Self::chain)
+    #[must_use]
+    #[inline]
+    pub fn digest(&self) -> u32 {
+        self.finish_32()
+    }
+
+    /// Feed everything `r` yields until EOF, returning the no. of bytes read
+    #[cfg(feature = "std")]
+    pub fn update_reader<R: Read>(&mut self, r: R) -> io::Result<u64> {
+        self.update_with_progress(r, |_| {})
+    }
+
+    /// [`update_reader`](Self::update_reader), calling `progress` with
+    /// [`total_len`](Self::total_len) after each buffer fill
+    #[cfg(feature = "std")]
+    pub fn update_with_progress<R: Read, F: FnMut(u64)>(
+        &mut self,
+        mut r: R,
+        mut progress: F,
+    ) -> io::Result<u64> {
+        let mut buf = vec![0; READ_BUF_SIZE];
+        let mut read = 0;
+
+        loop {
+            let n = match r.read(&mut buf) {
+                Ok(0) => return Ok(read),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            core::hash::Hasher::write(self, &buf[..n]);
+            read += n.into_u64();
+            progress(self.total_len());
+        }
+    }
+
     #[inline]
     #[must_use]
     fn finish_with(seed: u32, len: u64, accumulator: &Accumulator, mut data: &[u8]) -> u32 {
@@ -413,10 +532,11 @@ impl Hasher {
             accumulator.finish()
         };
 
-        acc += len as u32;
+        // the spec only mixes in the length mod 2^32
+        acc = acc.wrapping_add(truncate_u32(len));
 
         while let Some((chunk, rest)) = data.split_first_chunk() {
-            let lane = u32::from_ne_bytes(*chunk).to_le();
+            let lane = u32::from_le_bytes(*chunk);
 
             acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_3));
             acc = acc.rotate_left(17).wrapping_mul(PRIME32_4);
@@ -431,21 +551,25 @@ impl Hasher {
             acc = acc.rotate_left(11).wrapping_mul(PRIME32_1);
         }
 
-        acc ^= acc >> 15;
-        acc = acc.wrapping_mul(PRIME32_2);
-        acc ^= acc >> 13;
-        acc = acc.wrapping_mul(PRIME32_3);
-        acc ^= acc >> 16;
-
-        acc
+        avalanche32(acc)
     }
 }
 
+/// Only `write` is provided: slices and `str` keep std's default length
+/// prefix and `0xff` terminator, so composite keys stay unambiguous.
 impl core::hash::Hasher for Hasher {
     #[inline]
     fn write(&mut self, data: &[u8]) {
         let len = data.len().into_u64();
 
+        // a small key written to a fresh hasher only lands in the buffer,
+        // leaving `finish_with` to take its short input branch
+        if self.buffer.offset == 0 && data.len() < BYTES_IN_LANE {
+            self.buffer.set(data);
+            self.length += len;
+            return;
+        }
+
         let (buf_lanes, data) = self.buffer.extend(data);
 
         if let Some(&lanes) = buf_lanes {
@@ -464,7 +588,55 @@ impl core::hash::Hasher for Hasher {
     }
 }
 
-#[derive(Clone)]
+impl Extend<u8> for Hasher {
+    /// Bytes are collected into a small buffer, so each `write` gets a run
+    /// of them rather than a single byte
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        let mut buf = [0u8; 64];
+        let mut n = 0;
+
+        for byte in iter {
+            buf[n] = byte;
+            n += 1;
+
+            if n == buf.len() {
+                core::hash::Hasher::write(self, &buf);
+                n = 0;
+            }
+        }
+
+        core::hash::Hasher::write(self, &buf[..n]);
+    }
+}
+
+impl<'a> Extend<&'a [u8]> for Hasher {
+    fn extend<I: IntoIterator<Item = &'a [u8]>>(&mut self, iter: I) {
+        for chunk in iter {
+            core::hash::Hasher::write(self, chunk);
+        }
+    }
+}
+
+/// Hash whatever is written, e.g. `io::copy(&mut reader, &mut hasher)`
+#[cfg(feature = "std")]
+impl std::io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        core::hash::Hasher::write(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `BuildHasher` of [`Hasher`].
+///
+/// `finish` zero-extends the 32-bit digest, so the top bits std's `HashMap`
+/// takes its control bytes from are always 0 and probes compare more keys.
+/// Prefer [`xxhash64::State`](crate::xxhash64::State) for maps, see the
+/// `hashmap` bench.
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct State(u32);
 
 impl State {
@@ -482,6 +654,64 @@ impl BuildHasher for State {
     }
 }
 
+/// [`State`] with a random seed, for HashMaps exposed to untrusted keys.
+///
+/// The seed comes from std's `RandomState` entropy, so each instance gets a
+/// different one, while clones share it.
+#[cfg(feature = "std")]
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RandomState(State);
+
+#[cfg(feature = "std")]
+impl RandomState {
+    pub fn new() -> Self {
+        let seed = std::collections::hash_map::RandomState::new().hash_one(());
+
+        Self(State::with_seed(seed as u32))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for RandomState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl BuildHasher for RandomState {
+    type Hasher = Hasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        self.0.build_hasher()
+    }
+}
+
+/// Hash the raw UTF-8 bytes of `s`, same as `Hasher::oneshot(seed, s.as_bytes())`.
+///
+/// No length prefix or terminator is added, so this won't match hashing `s`
+/// through `Hash for str`, which writes a trailing `0xff`.
+#[must_use]
+#[inline]
+pub fn hash_str(s: &str, seed: u32) -> u32 {
+    Hasher::oneshot(seed, s.as_bytes())
+}
+
+/// The final mix of XXH32, spreading every input bit over the output. A
+/// bijection, usable on its own to scramble a 32-bit value such as a key's
+/// sign.
+#[must_use]
+#[inline]
+pub const fn avalanche32(mut x: u32) -> u32 {
+    x ^= x >> 15;
+    x = x.wrapping_mul(PRIME32_2);
+    x ^= x >> 13;
+    x = x.wrapping_mul(PRIME32_3);
+    x ^= x >> 16;
+
+    x
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -495,6 +725,12 @@ mod tests {
         const fn is_clone<T: Clone>() {}
         is_clone::<Hasher>();
         is_clone::<State>();
+        #[cfg(feature = "std")]
+        is_clone::<RandomState>();
+
+        const fn is_hash_key<T: core::hash::Hash + Eq>() {}
+        is_hash_key::<Hasher>();
+        is_hash_key::<State>();
     };
 
     const EMPTY_BYTES: [u8; 0] = [];
@@ -516,6 +752,24 @@ mod tests {
         assert_eq!(byte_by_byte, one_chunk);
     }
 
+    #[test]
+    fn chaining_is_equivalent_to_writes() {
+        let x = 0xdead_beef_u64;
+
+        let chained = Hasher::with_seed(7)
+            .chain(b"a")
+            .chain(&x.to_le_bytes())
+            .chain(&[0x2a; 20])
+            .digest();
+
+        let mut hasher = Hasher::with_seed(7);
+        hasher.write(b"a");
+        hasher.write(&x.to_le_bytes());
+        hasher.write(&[0x2a; 20]);
+
+        assert_eq!(chained, hasher.finish_32());
+    }
+
     #[test]
     fn hash_of_nothing_matches_c_implementation() {
         let mut hasher = Hasher::with_seed(0);
@@ -594,10 +848,154 @@ mod tests {
         assert_eq!(hasher.finish(), 0x1522_4ca7);
     }
 
+    #[test]
+    fn length_is_mixed_in_modulo_2_pow_32() {
+        // finishes to u32::MAX, so adding any length wraps around
+        let accumulator = Accumulator([u32::MAX, 0, 0, 0]);
+        let data = [7u8; 5];
+
+        let small = Hasher::finish_with(0, 16, &accumulator, &data);
+        let huge = Hasher::finish_with(0, (1 << 32) + 16, &accumulator, &data);
+
+        assert_eq!(small, huge);
+
+        let mut hasher = Hasher::with_seed(0);
+        hasher.write(&[0; 16]);
+        hasher.length += 1 << 32;
+
+        assert_eq!(hasher.total_len_32(), 16);
+        assert_eq!(hasher.finish_32(), Hasher::oneshot(0, &[0; 16]));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn random_states_are_seeded_differently() {
+        let states: Vec<RandomState> = (0..8).map(|_| RandomState::default()).collect();
+        let hashes: Vec<u64> = states.iter().map(|s| s.hash_one("key")).collect();
+
+        // all 8 random 32-bit seeds colliding is practically impossible
+        assert!(hashes.iter().any(|&h| h != hashes[0]));
+
+        let cloned = states[0].clone();
+        assert_eq!(cloned.hash_one("key"), hashes[0]);
+        assert_eq!(
+            State::with_seed(7).hash_one("key"),
+            State::with_seed(7).hash_one("key")
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn can_be_used_in_a_hashmap_with_a_random_seed() {
+        let mut hash: HashMap<_, _, RandomState> = Default::default();
+        hash.insert(42, "the answer");
+        assert_eq!(hash.get(&42), Some(&"the answer"));
+    }
+
+    #[test]
+    fn avalanche32_is_the_final_mix_and_does_not_collide() {
+        // empty input with seed 0 only goes through the final mix
+        assert_eq!(avalanche32(PRIME32_5), Hasher::oneshot(0, &[]));
+        const _: () = assert!(avalanche32(0) == 0);
+
+        let sampled = (0..1 << 16)
+            .chain((0..1 << 16).map(|i: u32| i.wrapping_mul(PRIME32_1)))
+            .collect::<std::collections::HashSet<_>>();
+        let mixed = sampled
+            .iter()
+            .map(|&x| avalanche32(x))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(mixed.len(), sampled.len());
+    }
+
+    #[test]
+    fn states_and_hashers_can_key_a_hashmap() {
+        let mut by_config: HashMap<State, &str> = HashMap::new();
+        by_config.insert(State::with_seed(1), "one");
+        by_config.insert(State::with_seed(2), "two");
+        by_config.insert(State::with_seed(1), "uno");
+
+        assert_eq!(by_config.len(), 2);
+        assert_eq!(by_config[&State::with_seed(1)], "uno");
+        assert_eq!(by_config[&State::with_seed(2)], "two");
+
+        // equal hashers hash equal, whatever they are fed in between
+        let a = Hasher::with_seed(3).chain(b"abc").chain(&[7; 20]);
+        let b = Hasher::with_seed(3).chain(b"abc").chain(&[7; 20]);
+        let state = State::with_seed(0);
+
+        assert_eq!(a, b);
+        assert_eq!(state.hash_one(&a), state.hash_one(&b));
+        assert_ne!(a, Hasher::with_seed(3).chain(b"abd").chain(&[7; 20]));
+    }
+
     #[test]
     fn can_be_used_in_a_hashmap_with_a_default_seed() {
         let mut hash: HashMap<_, _, BuildHasherDefault<Hasher>> = Default::default();
         hash.insert(42, "the answer");
         assert_eq!(hash.get(&42), Some(&"the answer"));
     }
+
+    #[test]
+    fn hash_str_hashes_raw_bytes() {
+        assert_eq!(hash_str("abc", 0), Hasher::oneshot(0, b"abc"));
+        assert_eq!(hash_str("", 7), Hasher::oneshot(7, &EMPTY_BYTES));
+    }
+
+    #[test]
+    fn composite_keys_do_not_collide() {
+        let state = State::with_seed(0);
+
+        let nested: [Vec<Vec<u8>>; 2] = [vec![vec![1, 2]], vec![vec![1], vec![2]]];
+        assert_ne!(state.hash_one(&nested[0]), state.hash_one(&nested[1]));
+
+        let strs: [Vec<&str>; 3] = [vec!["ab"], vec!["a", "b"], vec!["", "ab"]];
+        assert_ne!(state.hash_one(&strs[0]), state.hash_one(&strs[1]));
+        assert_ne!(state.hash_one(&strs[0]), state.hash_one(&strs[2]));
+        assert_ne!(state.hash_one(("ab", "")), state.hash_one(("a", "b")));
+
+        let mut map: HashMap<_, _, State> = HashMap::with_hasher(state);
+        for (i, key) in nested.into_iter().enumerate() {
+            map.insert(key, i);
+        }
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&vec![vec![1, 2]]], 0);
+        assert_eq!(map[&vec![vec![1], vec![2]]], 1);
+    }
+
+    #[test]
+    fn distinct_64_bit_seeds_give_distinct_digests() {
+        let seeds = [0, 1, 1 << 32, (1 << 32) | 1, u64::MAX];
+
+        let digests: Vec<_> = seeds
+            .iter()
+            .map(|&seed| Hasher::with_seed64(seed).chain(b"salted").digest())
+            .collect();
+
+        for (i, a) in digests.iter().enumerate() {
+            for b in &digests[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn progress_counts_increase_up_to_the_input_size() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+
+        let mut seen = Vec::new();
+        let mut hasher = Hasher::with_seed(0);
+        let read = hasher
+            .update_with_progress(&data[..], |total| seen.push(total))
+            .unwrap();
+
+        assert_eq!(read, data.len() as u64);
+        assert!(seen.len() > 1);
+        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(*seen.last().unwrap(), data.len() as u64);
+        assert_eq!(hasher.digest(), Hasher::oneshot(0, &data));
+    }
+
+    crate::testvectors::hasher_tests!(finish_32, 2);
 }
@@ -0,0 +1,436 @@
+use crate::IntoU64;
+use core::hash::BuildHasher;
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+type Lane = u64;
+type Lanes = [Lane; 4];
+type Bytes = [u8; 32];
+
+// compile time assertion backing the `Lanes` <-> `Bytes` casts of `BufferedData`
+const _: () = assert!(
+    core::mem::size_of::<Bytes>() == core::mem::size_of::<Lanes>()
+        && core::mem::align_of::<Bytes>() <= core::mem::align_of::<Lanes>()
+);
+
+const BYTES_IN_LANE: usize = core::mem::size_of::<Bytes>();
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct BufferedData(Lanes);
+
+impl BufferedData {
+    const fn new() -> Self {
+        Self([0; 4])
+    }
+
+    const fn bytes(&self) -> &Bytes {
+        unsafe { &*self.0.as_ptr().cast() }
+    }
+
+    fn bytes_mut(&mut self) -> &mut Bytes {
+        unsafe { &mut *self.0.as_mut_ptr().cast() }
+    }
+}
+
+impl core::fmt::Debug for BufferedData {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.0).finish()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+struct Buffer {
+    offset: usize,
+    data: BufferedData,
+}
+
+impl Buffer {
+    #[inline]
+    const fn new() -> Self {
+        Self {
+            offset: 0,
+            data: BufferedData::new(),
+        }
+    }
+
+    fn extend<'a>(&mut self, data: &'a [u8]) -> (Option<&Lanes>, &'a [u8]) {
+        if self.offset == 0 {
+            return (None, data);
+        }
+
+        let bytes = self.data.bytes_mut();
+        debug_assert!(self.offset <= bytes.len());
+
+        let empty = &mut bytes[self.offset..];
+        let n_to_copy = usize::min(empty.len(), data.len());
+
+        let dst = &mut empty[..n_to_copy];
+
+        let (src, rest) = data.split_at(n_to_copy);
+
+        dst.copy_from_slice(src);
+        self.offset += n_to_copy;
+
+        debug_assert!(self.offset <= bytes.len());
+
+        if self.offset == bytes.len() {
+            self.offset = 0;
+
+            return (Some(&self.data.0), rest);
+        }
+
+        (None, rest)
+    }
+
+    #[inline]
+    fn set(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        debug_assert_eq!(self.offset, 0);
+
+        let n_to_copy = data.len();
+
+        let bytes = self.data.bytes_mut();
+        debug_assert!(n_to_copy < bytes.len());
+
+        bytes[..n_to_copy].copy_from_slice(data);
+        self.offset = n_to_copy;
+    }
+
+    #[inline]
+    fn remaining(&self) -> &[u8] {
+        &self.data.bytes()[..self.offset]
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Accumulator(Lanes);
+
+impl Accumulator {
+    #[inline]
+    const fn new(seed: u64) -> Self {
+        Self([
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ])
+    }
+
+    /// `lanes` are as loaded from the input in native order, each is read as
+    /// little endian
+    #[inline]
+    fn write(&mut self, lanes: Lanes) {
+        self.write_loaded(lanes, cfg!(target_endian = "big"));
+    }
+
+    /// [`write`](Self::write) of `lanes` loaded on a host of the given
+    /// endianness, a big-endian one's being byte-swapped back
+    #[inline(always)]
+    fn write_loaded(&mut self, lanes: Lanes, big_endian: bool) {
+        let [acc1, acc2, acc3, acc4] = &mut self.0;
+        let [l1, l2, l3, l4] = match big_endian {
+            true => lanes.map(Lane::swap_bytes),
+            false => lanes,
+        };
+
+        *acc1 = Self::round(*acc1, l1);
+        *acc2 = Self::round(*acc2, l2);
+        *acc3 = Self::round(*acc3, l3);
+        *acc4 = Self::round(*acc4, l4);
+    }
+
+    #[inline]
+    fn write_many<'d>(&mut self, mut data: &'d [u8]) -> &'d [u8] {
+        while let Some((chunk, rest)) = data.split_first_chunk::<BYTES_IN_LANE>() {
+            let lanes = unsafe { chunk.as_ptr().cast::<Lanes>().read_unaligned() };
+            self.write(lanes);
+            data = rest;
+        }
+
+        data
+    }
+
+    #[inline]
+    const fn finish(&self) -> u64 {
+        let [acc1, acc2, acc3, acc4] = self.0;
+
+        let mut acc = acc1
+            .rotate_left(1)
+            .wrapping_add(acc2.rotate_left(7))
+            .wrapping_add(acc3.rotate_left(12))
+            .wrapping_add(acc4.rotate_left(18));
+
+        acc = Self::merge(acc, acc1);
+        acc = Self::merge(acc, acc2);
+        acc = Self::merge(acc, acc3);
+        Self::merge(acc, acc4)
+    }
+
+    #[inline]
+    const fn round(mut acc: u64, lane: u64) -> u64 {
+        acc = acc.wrapping_add(lane.wrapping_mul(PRIME64_2));
+        acc = acc.rotate_left(31);
+        acc.wrapping_mul(PRIME64_1)
+    }
+
+    #[inline]
+    const fn merge(mut acc: u64, acc_n: u64) -> u64 {
+        acc ^= Self::round(0, acc_n);
+        acc = acc.wrapping_mul(PRIME64_1);
+        acc.wrapping_add(PRIME64_4)
+    }
+}
+
+impl core::fmt::Debug for Accumulator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [acc1, acc2, acc3, acc4] = self.0;
+
+        f.debug_struct("Accumulator")
+            .field("acc1", &acc1)
+            .field("acc2", &acc2)
+            .field("acc3", &acc3)
+            .field("acc4", &acc4)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Hasher {
+    seed: u64,
+    length: u64,
+    accumulator: Accumulator,
+    buffer: Buffer,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::with_seed(0)
+    }
+}
+
+impl Hasher {
+    /// Hash all data at once and get a 64-bit hash value
+    #[must_use]
+    #[inline]
+    pub fn oneshot(seed: u64, data: &[u8]) -> u64 {
+        let len = data.len().into_u64();
+
+        let mut accumulator = Accumulator::new(seed);
+        let data = accumulator.write_many(data);
+
+        Self::finish_with(seed, len, &accumulator, data)
+    }
+
+    /// Construct the hasher with initial seed
+    #[must_use]
+    pub const fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            length: 0,
+            accumulator: Accumulator::new(seed),
+            buffer: Buffer::new(),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    fn finish_with(seed: u64, len: u64, accumulator: &Accumulator, mut data: &[u8]) -> u64 {
+        let mut acc = if len < BYTES_IN_LANE.into_u64() {
+            seed.wrapping_add(PRIME64_5)
+        } else {
+            accumulator.finish()
+        };
+
+        acc = acc.wrapping_add(len);
+
+        while let Some((chunk, rest)) = data.split_first_chunk() {
+            let lane = u64::from_le_bytes(*chunk);
+
+            acc ^= Accumulator::round(0, lane);
+            acc = acc.rotate_left(27).wrapping_mul(PRIME64_1);
+            acc = acc.wrapping_add(PRIME64_4);
+
+            data = rest;
+        }
+
+        if let Some((chunk, rest)) = data.split_first_chunk() {
+            let lane = u32::from_le_bytes(*chunk).into_u64();
+
+            acc ^= lane.wrapping_mul(PRIME64_1);
+            acc = acc.rotate_left(23).wrapping_mul(PRIME64_2);
+            acc = acc.wrapping_add(PRIME64_3);
+
+            data = rest;
+        }
+
+        for &byte in data {
+            let lane = byte.into_u64();
+
+            acc ^= lane.wrapping_mul(PRIME64_5);
+            acc = acc.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        acc ^= acc >> 33;
+        acc = acc.wrapping_mul(PRIME64_2);
+        acc ^= acc >> 29;
+        acc = acc.wrapping_mul(PRIME64_3);
+        acc ^= acc >> 32;
+
+        acc
+    }
+}
+
+impl core::hash::Hasher for Hasher {
+    #[inline]
+    fn write(&mut self, data: &[u8]) {
+        let len = data.len().into_u64();
+
+        // a small key written to a fresh hasher only lands in the buffer,
+        // leaving `finish_with` to take its short input branch
+        if self.buffer.offset == 0 && data.len() < BYTES_IN_LANE {
+            self.buffer.set(data);
+            self.length += len;
+            return;
+        }
+
+        let (buf_lanes, data) = self.buffer.extend(data);
+
+        if let Some(&lanes) = buf_lanes {
+            self.accumulator.write(lanes);
+        }
+
+        let data = self.accumulator.write_many(data);
+
+        self.buffer.set(data);
+        self.length += len;
+    }
+
+    /// A snapshot of the digest so far, more data can be written after it
+    #[inline]
+    fn finish(&self) -> u64 {
+        Self::finish_with(
+            self.seed,
+            self.length,
+            &self.accumulator,
+            self.buffer.remaining(),
+        )
+    }
+}
+
+impl Extend<u8> for Hasher {
+    /// Bytes are collected into a small buffer, so each `write` gets a run
+    /// of them rather than a single byte
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        let mut buf = [0u8; 64];
+        let mut n = 0;
+
+        for byte in iter {
+            buf[n] = byte;
+            n += 1;
+
+            if n == buf.len() {
+                core::hash::Hasher::write(self, &buf);
+                n = 0;
+            }
+        }
+
+        core::hash::Hasher::write(self, &buf[..n]);
+    }
+}
+
+impl<'a> Extend<&'a [u8]> for Hasher {
+    fn extend<I: IntoIterator<Item = &'a [u8]>>(&mut self, iter: I) {
+        for chunk in iter {
+            core::hash::Hasher::write(self, chunk);
+        }
+    }
+}
+
+/// Hash whatever is written, e.g. `io::copy(&mut reader, &mut hasher)`
+#[cfg(feature = "std")]
+impl std::io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        core::hash::Hasher::write(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct State(u64);
+
+impl State {
+    /// Constructs the hasher w/ an initial seed.
+    pub fn with_seed(seed: u64) -> Self {
+        Self(seed)
+    }
+}
+
+impl BuildHasher for State {
+    type Hasher = Hasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        Hasher::with_seed(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{
+        array,
+        hash::{BuildHasherDefault, Hasher as _},
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn ingesting_byte_by_byte_is_equivalent_to_large_chunks() {
+        let bytes: Vec<u8> = (0..200u8).collect();
+
+        let mut byte_by_byte = Hasher::with_seed(0);
+        for byte in bytes.chunks(1) {
+            byte_by_byte.write(byte);
+        }
+
+        let mut one_chunk = Hasher::with_seed(0);
+        one_chunk.write(&bytes);
+
+        assert_eq!(byte_by_byte.finish(), one_chunk.finish());
+        assert_eq!(one_chunk.finish(), Hasher::oneshot(0, &bytes));
+    }
+
+    #[test]
+    fn hashes_with_different_offsets_are_the_same() {
+        let bytes = [0x7c; 4096];
+        let expected = Hasher::oneshot(0, &[0x7c; 64]);
+
+        let the_same = bytes
+            .windows(64)
+            .map(|w| {
+                let mut hasher = Hasher::with_seed(0);
+                hasher.write(w);
+                hasher.finish()
+            })
+            .all(|h| h == expected);
+        assert!(the_same);
+    }
+
+    #[test]
+    fn can_be_used_in_a_hashmap_with_a_default_seed() {
+        let mut hash: HashMap<_, _, BuildHasherDefault<Hasher>> = Default::default();
+        hash.insert(42, "the answer");
+        assert_eq!(hash.get(&42), Some(&"the answer"));
+    }
+
+    crate::testvectors::hasher_tests!(finish, 3);
+}
@@ -34,7 +34,7 @@ fn file_read(c: &mut Criterion) {
         g.throughput(Throughput::Bytes(size as u64));
         g.bench_with_input(BenchmarkId::from_parameter(id), &path, |b, path| {
             b.iter(|| {
-                let mut sr = SrcReader::new(&path).unwrap();
+                let mut sr = SrcReader::new(path).unwrap();
 
                 while let Some(chunk) = sr.get_chunk() {
                     black_box(chunk);
@@ -32,8 +32,10 @@ fn file_read(c: &mut Criterion) {
 
         g.throughput(Throughput::Bytes(size as u64));
         g.bench_with_input(BenchmarkId::from_parameter(id), &path, |b, path| {
+            let mut tokenizer = Tokenizer::new();
+
             b.iter(|| {
-                let tokens = Tokenizer::tokenize(path).unwrap();
+                let tokens = tokenizer.tokenize(path).unwrap();
                 black_box(tokens);
             });
         });
@@ -42,5 +44,31 @@ fn file_read(c: &mut Criterion) {
     g.finish();
 }
 
-criterion_group!(bench, file_read);
+fn bytes_read(c: &mut Criterion) {
+    let mut g = c.my_benchmark_group("y3", "tokenize_bytes");
+    let pattern = b"alpha-beta_gamma\tdelta\r\nlorem ipsum dolor\n";
+
+    for mib in [1, 4, 16] {
+        let size = mib * 1024 * 1024;
+        let data: Vec<u8> = pattern.iter().copied().cycle().take(size).collect();
+
+        g.throughput(Throughput::Bytes(size as u64));
+        g.bench_with_input(
+            BenchmarkId::from_parameter(format!("{mib}MiB")),
+            &data,
+            |b, data| {
+                let mut tokenizer = Tokenizer::new();
+
+                b.iter(|| {
+                    let tokens = tokenizer.tokenize_bytes(data);
+                    black_box(tokens);
+                });
+            },
+        );
+    }
+
+    g.finish();
+}
+
+criterion_group!(bench, file_read, bytes_read);
 criterion_main!(bench);